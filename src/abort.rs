@@ -0,0 +1,87 @@
+use std::{
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/**
+ * This struct is a handle to abort the transfers of one or more FtpStreams from another
+ * thread, e.g. on Ctrl-C. Aborting shuts down the data channels in use, so a transfer that
+ * waits for data stops at once, and the FtpStream then sends ABOR and reads the replies,
+ * so the control channel stays usable. Once aborted, every new transfer fails until reset.
+ */
+#[derive(Clone, Default)]
+pub struct AbortHandle {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    aborted: AtomicBool,
+    // The data channels in use, with the key of the Watch that removes them
+    streams: Mutex<Vec<(u64, TcpStream)>>,
+    next_key: AtomicU64,
+}
+
+impl AbortHandle {
+    /**
+     * This function is to abort the transfers in progress and the ones to come.
+     */
+    pub fn abort(&self) {
+        self.shared.aborted.store(true, Ordering::SeqCst);
+        for (_, stream) in self.shared.streams.lock().unwrap().iter() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /**
+     * This function is to tell whether the transfers are aborted.
+     */
+    pub fn is_aborted(&self) -> bool {
+        self.shared.aborted.load(Ordering::SeqCst)
+    }
+
+    /**
+     * This function is to allow transfers again after an abort.
+     */
+    #[allow(unused)]
+    pub fn reset(&self) {
+        self.shared.aborted.store(false, Ordering::SeqCst);
+    }
+
+    /**
+     * This function is to register a data channel, so abort can shut it down while it's in
+     * use. It's shut down at once if the transfers are already aborted.
+     * Return the Watch that unregisters the data channel when it's dropped.
+     */
+    pub fn watch(&self, stream: &TcpStream) -> Watch {
+        let key = self.shared.next_key.fetch_add(1, Ordering::Relaxed);
+        if let Ok(stream) = stream.try_clone() {
+            self.shared.streams.lock().unwrap().push((key, stream));
+        }
+        // Checked after the registration, so an abort in between isn't missed
+        if self.is_aborted() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        Watch {
+            shared: self.shared.clone(),
+            key,
+        }
+    }
+}
+
+/**
+ * This struct keeps a data channel registered to an AbortHandle until it's dropped.
+ */
+pub struct Watch {
+    shared: Arc<Shared>,
+    key: u64,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.shared.streams.lock().unwrap().retain(|(key, _)| *key != self.key);
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::{self, Write};
+
+/**
+ * This struct converts the CRLF line endings of the data written to it to LF, which is
+ * how a file received in ASCII mode (TYPE A) is stored locally. A CR that isn't followed
+ * by LF is kept. Call finish() after the last write, so a trailing CR isn't lost.
+ */
+pub struct ToLf<W: Write> {
+    inner: W,
+    // The last byte written was a CR, it's held back until the next byte is known
+    pending_cr: bool,
+}
+
+impl<W: Write> ToLf<W> {
+    pub fn new(inner: W) -> Self {
+        ToLf { inner, pending_cr: false }
+    }
+
+    /**
+     * This function is to write the CR held back at the end of the data, if any.
+     */
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.inner.write_all(b"\r")?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for ToLf<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = Vec::with_capacity(buf.len() + 1);
+        for &b in buf {
+            if self.pending_cr && b != b'\n' {
+                out.push(b'\r');
+            }
+            self.pending_cr = b == b'\r';
+            if !self.pending_cr {
+                out.push(b);
+            }
+        }
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/**
+ * This struct converts the LF line endings of the data written to it to CRLF, which is
+ * how a file is sent in ASCII mode (TYPE A). Line endings that already are CRLF are kept.
+ */
+pub struct ToCrlf<W: Write> {
+    inner: W,
+    // The last byte written was a CR
+    last_cr: bool,
+}
+
+impl<W: Write> ToCrlf<W> {
+    pub fn new(inner: W) -> Self {
+        ToCrlf { inner, last_cr: false }
+    }
+}
+
+impl<W: Write> Write for ToCrlf<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = Vec::with_capacity(buf.len() + buf.len() / 16);
+        for &b in buf {
+            if b == b'\n' && !self.last_cr {
+                out.push(b'\r');
+            }
+            out.push(b);
+            self.last_cr = b == b'\r';
+        }
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Files with these extensions are transferred in ASCII mode by --auto-type
+const TEXT_EXTENSIONS: [&str; 26] = [
+    "txt", "text", "csv", "tsv", "log", "md", "htm", "html", "xml", "json", "yml", "yaml", "ini",
+    "cfg", "conf", "sh", "bat", "c", "h", "cpp", "rs", "py", "java", "js", "css", "sql",
+];
+
+/**
+ * This function is to tell whether a file is text by its extension.
+ * path: The path or name of the file.
+ * Return true if the extension is one of TEXT_EXTENSIONS, ignoring case.
+ */
+pub fn is_text_file(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((_, ext)) => TEXT_EXTENSIONS.iter().any(|t| t.eq_ignore_ascii_case(ext)),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_lf(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut lf = ToLf::new(&mut out);
+        for chunk in chunks {
+            lf.write_all(chunk).unwrap();
+        }
+        lf.finish().unwrap();
+        out
+    }
+
+    fn to_crlf(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut crlf = ToCrlf::new(&mut out);
+        for chunk in chunks {
+            crlf.write_all(chunk).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn to_lf_converts_crlf() {
+        assert_eq!(to_lf(&[b"a\r\nb\r\n"]), b"a\nb\n");
+        assert_eq!(to_lf(&[b"a\nb\rc"]), b"a\nb\rc");
+    }
+
+    #[test]
+    fn to_lf_cr_split_across_writes() {
+        assert_eq!(to_lf(&[b"a\r", b"\nb"]), b"a\nb");
+        assert_eq!(to_lf(&[b"a\r", b"b"]), b"a\rb");
+        assert_eq!(to_lf(&[b"a\r", b"", b"\n"]), b"a\n");
+    }
+
+    #[test]
+    fn to_lf_cr_cr_lf() {
+        assert_eq!(to_lf(&[b"a\r\r\nb"]), b"a\r\nb");
+        assert_eq!(to_lf(&[b"a\r", b"\r", b"\nb"]), b"a\r\nb");
+    }
+
+    #[test]
+    fn to_lf_finish_flushes_trailing_cr() {
+        assert_eq!(to_lf(&[b"a\r"]), b"a\r");
+        let mut out = vec![];
+        ToLf::new(&mut out).write_all(b"a\r").unwrap();
+        assert_eq!(out, b"a");
+    }
+
+    #[test]
+    fn to_crlf_converts_lf() {
+        assert_eq!(to_crlf(&[b"a\nb\n"]), b"a\r\nb\r\n");
+        assert_eq!(to_crlf(&[b"\n\n"]), b"\r\n\r\n");
+    }
+
+    #[test]
+    fn to_crlf_keeps_crlf() {
+        assert_eq!(to_crlf(&[b"a\r\nb\r\n"]), b"a\r\nb\r\n");
+        assert_eq!(to_crlf(&[b"a\r", b"\nb\n"]), b"a\r\nb\r\n");
+        assert_eq!(to_crlf(&[b"a\rb"]), b"a\rb");
+    }
+
+    #[test]
+    fn round_trip() {
+        let text: &[u8] = b"line 1\nline 2\r\n\nend";
+        assert_eq!(to_lf(&[&to_crlf(&[text])]), b"line 1\nline 2\n\nend");
+    }
+
+    #[test]
+    fn text_files_by_extension() {
+        assert!(is_text_file("dir/notes.TXT"));
+        assert!(!is_text_file("archive.tar.gz"));
+        assert!(!is_text_file("txt"));
+        assert!(!is_text_file("dir.txt/binary"));
+    }
+}
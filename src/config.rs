@@ -0,0 +1,197 @@
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+use encoding_rs::Encoding;
+use serde::{Deserialize, Deserializer};
+
+use crate::url::FtpUrl;
+
+/**
+ * This struct is the configuration file, ~/.config/4700ftp/config.toml by default, which
+ * names the servers that are used often:
+ *
+ *     [profiles.vendor]
+ *     url = "ftp://ftp.vendor.com/outgoing"
+ *     user = "acme"
+ *     password_env = "VENDOR_FTP_PASSWORD"
+ *     port = 2121
+ *     timeout = 30
+ *     encoding = "latin1"
+ *
+ * A profile is used as "@vendor" or "@vendor/inbox" in place of an URL.
+ */
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+/**
+ * This struct is a named server of the configuration file. Everything but the URL is
+ * optional, and the command line arguments take precedence over it.
+ */
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// The URL of the server, the path of "@name/path" is relative to the path of this URL
+    pub url: String,
+    /// The username, if the URL has none
+    pub user: Option<String>,
+    /// The environment variable holding the password, so it's not written in the file
+    pub password_env: Option<String>,
+    /// The port, if the URL has none
+    pub port: Option<u16>,
+    /// Connect with FTPS. Not supported yet, so only false is accepted
+    pub tls: Option<bool>,
+    /// Use passive mode for the data channels. Only passive mode is supported
+    pub passive: Option<bool>,
+    /// The timeout in seconds for connecting and for every read and write
+    pub timeout: Option<u64>,
+    /// The interval in seconds of the NOOPs sent during a transfer
+    pub keepalive: Option<u64>,
+    /// The encoding of the paths, server messages and listings
+    #[serde(default, deserialize_with = "deserialize_encoding")]
+    pub encoding: Option<&'static Encoding>,
+}
+
+impl Config {
+    /**
+     * This function is to read the configuration file.
+     * path: The file given with --config, otherwise $XDG_CONFIG_HOME/4700ftp/config.toml
+     * or ~/.config/4700ftp/config.toml is used.
+     * Return Ok(Config) if the file is read and valid, otherwise Err(String) with error message.
+     */
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+                .ok_or("can't find the configuration file, neither HOME nor XDG_CONFIG_HOME is set")?
+                .join("4700ftp")
+                .join("config.toml"),
+        };
+        let content = fs::read_to_string(&path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /**
+     * This function is to resolve "@name" or "@name/path" to the URL and the profile of the name.
+     * The path is not percent-decoded, and is appended to the path of the profile's URL.
+     * param: The parameter starting with '@'.
+     * Return Ok((FtpUrl, &Profile)) if the profile exists and is valid,
+     * otherwise Err(String) with error message.
+     */
+    pub fn resolve(&self, param: &str) -> Result<(FtpUrl, &Profile), String> {
+        let (name, path) = match param[1..].split_once('/') {
+            Some((name, path)) => (name, Some(path)),
+            None => (&param[1..], None),
+        };
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| {
+                format!(
+                    "no profile named {} in the configuration file, write ./{} if it's a local path",
+                    name, param
+                )
+            })?;
+
+        if profile.tls == Some(true) {
+            return Err(format!("profile {}: TLS is not supported", name));
+        }
+        if profile.passive == Some(false) {
+            return Err(format!("profile {}: active mode is not supported", name));
+        }
+
+        let mut url = FtpUrl::parse(&profile.url).map_err(|e| format!("profile {}: {}", name, e))?;
+        url.user = url.user.or_else(|| profile.user.clone());
+        url.port = url.port.or(profile.port);
+        if let Some(path) = path {
+            if url.segments.last().is_some_and(|s| s.is_empty()) {
+                url.segments.pop();
+            }
+            url.segments.extend(path.split('/').map(String::from));
+        }
+
+        Ok((url, profile))
+    }
+}
+
+/**
+ * This function is to tell whether the given parameter is meant to be a profile of the
+ * configuration file, i.e. it starts with '@'.
+ */
+pub fn is_profile(param: &str) -> bool {
+    param.starts_with('@')
+}
+
+/**
+ * This function is to look up the encoding of a profile by its label.
+ */
+fn deserialize_encoding<'de, D: Deserializer<'de>>(d: D) -> Result<Option<&'static Encoding>, D::Error> {
+    let label = String::deserialize(d)?;
+    Encoding::for_label(label.as_bytes())
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown encoding {}", label)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(profile: &str) -> Config {
+        toml::from_str(&format!("[profiles.vendor]\n{}", profile)).unwrap()
+    }
+
+    fn path(config: &Config, param: &str) -> String {
+        config.resolve(param).unwrap().0.path()
+    }
+
+    #[test]
+    fn profile_path_is_joined() {
+        let config = config("url = \"ftp://ftp.vendor.com/outgoing\"");
+        assert_eq!(path(&config, "@vendor"), "outgoing");
+        assert_eq!(path(&config, "@vendor/"), "outgoing/");
+        assert_eq!(path(&config, "@vendor/a/b"), "outgoing/a/b");
+    }
+
+    #[test]
+    fn profile_url_with_trailing_slash() {
+        let config = config("url = \"ftp://ftp.vendor.com/outgoing/\"");
+        assert_eq!(path(&config, "@vendor"), "outgoing/");
+        assert_eq!(path(&config, "@vendor/"), "outgoing/");
+        assert_eq!(path(&config, "@vendor/a/b"), "outgoing/a/b");
+    }
+
+    #[test]
+    fn user_and_port_of_profile() {
+        let profile = config("url = \"ftp://ftp.vendor.com\"\nuser = \"acme\"\nport = 2121");
+        let (url, _) = profile.resolve("@vendor/a").unwrap();
+        assert_eq!(url.user.as_deref(), Some("acme"));
+        assert_eq!(url.port, Some(2121));
+        assert_eq!(url.path(), "a");
+
+        // The URL takes precedence over the profile
+        let other = config("url = \"ftp://bob@ftp.vendor.com:21\"\nuser = \"acme\"\nport = 2121");
+        let (url, _) = other.resolve("@vendor").unwrap();
+        assert_eq!(url.user.as_deref(), Some("bob"));
+        assert_eq!(url.port, Some(21));
+    }
+
+    #[test]
+    fn unsupported_settings_are_rejected() {
+        let tls = config("url = \"ftp://ftp.vendor.com\"\ntls = true");
+        assert!(tls.resolve("@vendor").unwrap_err().contains("TLS is not supported"));
+        let active = config("url = \"ftp://ftp.vendor.com\"\npassive = false");
+        assert!(active.resolve("@vendor").unwrap_err().contains("active mode is not supported"));
+        let plain = config("url = \"ftp://ftp.vendor.com\"\ntls = false\npassive = true");
+        assert!(plain.resolve("@vendor").is_ok());
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let config = config("url = \"ftp://ftp.vendor.com\"");
+        assert!(config.resolve("@other/file").unwrap_err().contains("write ./@other/file"));
+    }
+}
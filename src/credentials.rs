@@ -0,0 +1,234 @@
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use crate::{config::Profile, url::FtpUrl};
+
+/**
+ * This struct is the username and password used to login a Ftp server. They are looked
+ * up in this order, the first one found is used:
+ *
+ *     username: the URL, the profile, FTP_USER, ~/.netrc, "anonymous"
+ *     password: the URL, --password-stdin, the profile's password_env, FTP_PASSWORD, ~/.netrc,
+ *               a prompt on the terminal
+ *
+ * The password from ~/.netrc is only used if the entry's login is the username. The prompt
+ * only happens when the server asks for a password, and only once for every connection.
+ */
+pub struct Credentials {
+    pub username: String,
+    password: Option<String>,
+    host: String,
+    // The password typed at the prompt, shared by every connection
+    prompted: Mutex<Option<String>>,
+}
+
+impl Credentials {
+    /**
+     * This function is to look up the credentials for the given URL.
+     * url: The URL to login to, with the user of the profile if the URL has none.
+     * profile: The profile of the configuration file the URL comes from, if any.
+     * stdin_password: The password read from stdin with --password-stdin, if any.
+     */
+    pub fn resolve(url: &FtpUrl, profile: &Profile, stdin_password: Option<String>) -> Self {
+        let netrc = netrc_lookup(&url.host);
+        let username = url
+            .user
+            .clone()
+            .or_else(|| env::var("FTP_USER").ok())
+            .or_else(|| netrc.as_ref().and_then(|(login, _)| login.clone()))
+            .unwrap_or_else(|| "anonymous".to_string());
+        let password = url
+            .password
+            .clone()
+            .or(stdin_password)
+            .or_else(|| profile.password_env.as_ref().and_then(|name| env::var(name).ok()))
+            .or_else(|| env::var("FTP_PASSWORD").ok())
+            .or_else(|| match netrc {
+                Some((login, password)) if login.is_none() || login.as_ref() == Some(&username) => password,
+                _ => None,
+            });
+
+        Credentials {
+            username,
+            password,
+            host: url.host.clone(),
+            prompted: Mutex::new(None),
+        }
+    }
+
+    /**
+     * This function is to get the password, prompting for it without echo if none was given.
+     * There is no prompt if there is no terminal or the user is anonymous, and the password
+     * is empty instead.
+     * Return Ok(String) with the password, otherwise Err(String) if the prompt fails.
+     */
+    pub fn password(&self) -> Result<String, String> {
+        if let Some(password) = &self.password {
+            return Ok(password.clone());
+        }
+
+        // The lock is held during the prompt, so parallel connections only prompt once
+        let mut prompted = self.prompted.lock().unwrap();
+        if let Some(password) = &*prompted {
+            return Ok(password.clone());
+        }
+        let password = match self.username != "anonymous" && has_terminal() {
+            true => rpassword::prompt_password(format!("Password for {}@{}: ", self.username, self.host))
+                .map_err(|e| format!("can't read the password: {}", e))?,
+            false => String::new(),
+        };
+        *prompted = Some(password.clone());
+        Ok(password)
+    }
+}
+
+/**
+ * This function is to tell whether there is a terminal to prompt on. The prompt is read
+ * from the terminal itself (/dev/tty on Unix) rather than stdin, so stdin may carry a file
+ * being uploaded and the prompt still works.
+ */
+fn has_terminal() -> bool {
+    if cfg!(unix) {
+        fs::File::options().read(true).write(true).open("/dev/tty").is_ok()
+    } else {
+        io::stdin().is_terminal()
+    }
+}
+
+/**
+ * This function is to read the password given with --password-stdin, which is the first
+ * line of stdin without its line ending.
+ */
+pub fn read_stdin_password() -> Result<String, String> {
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .map_err(|e| format!("can't read the password from stdin: {}", e))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/**
+ * This function is to find the entry of a host in the netrc file, which is $NETRC or
+ * ~/.netrc (see parse_netrc).
+ * host: The host to look up, ignoring case.
+ * Return Some((login, password)) if there is an entry, otherwise None.
+ */
+fn netrc_lookup(host: &str) -> Option<(Option<String>, Option<String>)> {
+    let path = env::var_os("NETRC")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc")))?;
+    let content = fs::read_to_string(path).ok()?;
+    parse_netrc(&content, host)
+}
+
+/**
+ * This function is to find the entry of a host in the content of a netrc file. The entry
+ * of the host is used, or the "default" entry if there is none. The entry of another
+ * machine is never used.
+ * content: The content of the netrc file.
+ * host: The host to look up, ignoring case.
+ * Return Some((login, password)) if there is an entry, otherwise None.
+ */
+fn parse_netrc(content: &str, host: &str) -> Option<(Option<String>, Option<String>)> {
+    // The tokens of the file, the body of a macdef (up to the next empty line) is skipped
+    let mut tokens = vec![];
+    let mut in_macdef = false;
+    for line in content.lines() {
+        if in_macdef {
+            in_macdef = !line.trim().is_empty();
+            continue;
+        }
+        for word in line.split_whitespace() {
+            if word == "macdef" {
+                in_macdef = true;
+                break;
+            }
+            tokens.push(word);
+        }
+    }
+
+    // The entry being read: whether it's the host, whether it's default, its login and password
+    let mut entry: Option<(bool, bool, Option<String>, Option<String>)> = None;
+    let mut default = None;
+    let mut tokens = tokens.into_iter();
+    loop {
+        let token = tokens.next();
+        if matches!(token, None | Some("machine") | Some("default")) {
+            // The entry ends where the next one starts, or at the end of the file
+            match entry.take() {
+                Some((true, _, login, password)) => return Some((login, password)),
+                Some((false, true, login, password)) if default.is_none() => default = Some((login, password)),
+                _ => {}
+            }
+        }
+        match token {
+            None => return default,
+            Some("machine") => {
+                let is_host = tokens.next().is_some_and(|m| m.eq_ignore_ascii_case(host));
+                entry = Some((is_host, false, None, None));
+            }
+            Some("default") => entry = Some((false, true, None, None)),
+            Some("login") => {
+                if let Some(e) = entry.as_mut() {
+                    e.2 = tokens.next().map(|t| t.to_string());
+                }
+            }
+            Some("password") => {
+                if let Some(e) = entry.as_mut() {
+                    e.3 = tokens.next().map(|t| t.to_string());
+                }
+            }
+            Some("account") => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(login: &str, password: &str) -> Option<(Option<String>, Option<String>)> {
+        Some((Some(login.to_string()), Some(password.to_string())))
+    }
+
+    #[test]
+    fn netrc_matching_host() {
+        let netrc = "machine a.example.com login alice password pa\nmachine B.example.com login bob password pb\n";
+        assert_eq!(parse_netrc(netrc, "a.example.com"), entry("alice", "pa"));
+        assert_eq!(parse_netrc(netrc, "b.example.com"), entry("bob", "pb"));
+    }
+
+    #[test]
+    fn netrc_other_host_is_never_used() {
+        let netrc = "machine a.example.com login alice password pa\nmachine b.example.com login bob password pb\n";
+        assert_eq!(parse_netrc(netrc, "c.example.com"), None);
+        assert_eq!(parse_netrc("machine a.example.com login alice password pa", "c.example.com"), None);
+    }
+
+    #[test]
+    fn netrc_default() {
+        let netrc = "machine a.example.com login alice password pa\ndefault login anonymous password guest\n";
+        assert_eq!(parse_netrc(netrc, "c.example.com"), entry("anonymous", "guest"));
+        assert_eq!(parse_netrc(netrc, "a.example.com"), entry("alice", "pa"));
+        let netrc = "default login anonymous password guest\nmachine a.example.com login alice password pa\n";
+        assert_eq!(parse_netrc(netrc, "c.example.com"), entry("anonymous", "guest"));
+        assert_eq!(parse_netrc(netrc, "a.example.com"), entry("alice", "pa"));
+    }
+
+    #[test]
+    fn netrc_macdef() {
+        let netrc = "machine a.example.com login alice password pa\n\
+                     macdef init\nmachine c.example.com login mallory password pm\n\n\
+                     machine b.example.com account x login bob password pb\n";
+        assert_eq!(parse_netrc(netrc, "c.example.com"), None);
+        assert_eq!(parse_netrc(netrc, "a.example.com"), entry("alice", "pa"));
+        assert_eq!(parse_netrc(netrc, "b.example.com"), entry("bob", "pb"));
+    }
+}
@@ -0,0 +1,206 @@
+use std::{fmt, process::ExitCode};
+
+use crate::ftp::{FtpError, FtpReply};
+
+/**
+ * This enum is the class of a failure, which decides the exit code of the process.
+ * Every code is listed in the help (--help) as well.
+ */
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    /// 1: Any other failure, e.g. the server rejected a command
+    General = 1,
+    /// 2: Invalid arguments, URL, profile or configuration file
+    Usage = 2,
+    /// 3: Can't connect to the server, or the connection is lost
+    Connection = 3,
+    /// 4: The server rejected the username or the password
+    Auth = 4,
+    /// 5: The file or directory doesn't exist on the server
+    NotFound = 5,
+    /// 6: The server denied the access to the file or directory
+    Permission = 6,
+    /// 7: A local file can't be read or written
+    LocalIo = 7,
+    /// 8: The server didn't answer within --timeout
+    Timeout = 8,
+    /// 9: The copy doesn't match the source
+    Verification = 9,
+    /// 130: Ctrl-C aborted the transfers
+    Interrupted = 130,
+}
+
+/**
+ * This is the list of the exit codes shown in the help.
+ */
+pub const EXIT_CODES: &str = "Exit codes:
+  0  Success
+  1  Any other failure, e.g. the server rejected a command
+  2  Invalid arguments, URL, profile or configuration file
+  3  Can't connect to the server, or the connection is lost
+  4  The server rejected the username or the password
+  5  The file or directory doesn't exist on the server
+  6  The server denied the access to the file or directory
+  7  A local file can't be read or written
+  8  The server didn't answer within --timeout
+  9  The copy doesn't match the source (--verify)
+130  Ctrl-C aborted the transfers, press it twice to quit at once";
+
+impl From<&FtpError> for Kind {
+    /**
+     * This function is to tell the class of an error of FtpStream. The variant tells it,
+     * except for a reply the server rejected a command with, whose code tells it. 450 and
+     * 550 mean either no such file or no access, which only the text of the reply tells apart.
+     */
+    fn from(e: &FtpError) -> Self {
+        match e {
+            FtpError::Connection(_) => Kind::Connection,
+            FtpError::Timeout(_) => Kind::Timeout,
+            FtpError::Auth(_) => Kind::Auth,
+            FtpError::LocalIo(_) => Kind::LocalIo,
+            FtpError::Verify(_) => Kind::Verification,
+            FtpError::Aborted(_) => Kind::Interrupted,
+            FtpError::Other(_) => Kind::General,
+            FtpError::Reply(FtpReply { code, text }) | FtpError::TransferFailed { code, reply: text } => match code {
+                421 => Kind::Connection,
+                530 | 332 => Kind::Auth,
+                553 | 532 => Kind::Permission,
+                450 | 550 if is_denied(text) => Kind::Permission,
+                450 | 550 => Kind::NotFound,
+                _ => Kind::General,
+            },
+        }
+    }
+}
+
+/**
+ * This function is to tell whether the last line of a reply says the access is denied,
+ * e.g. "550 /etc/shadow: Permission denied.". Only the end is checked, as the path in the
+ * reply may contain any word.
+ */
+fn is_denied(reply: &str) -> bool {
+    let last = reply.lines().last().unwrap_or("").trim_end().trim_end_matches('.').to_lowercase();
+    ["permission denied", "access denied", "not allowed"].iter().any(|w| last.ends_with(w))
+}
+
+/**
+ * This struct is an error of the CLI with its class, so main can exit with its code, and
+ * the code of the server reply it came from, if any.
+ */
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: Kind,
+    pub message: String,
+    pub code: Option<u16>,
+}
+
+impl Failure {
+    /**
+     * This function is to create the error of invalid arguments.
+     */
+    pub fn usage(message: impl Into<String>) -> Self {
+        Failure { kind: Kind::Usage, message: message.into(), code: None }
+    }
+
+    /**
+     * This function is to put what was being done in front of the message, e.g.
+     * "a.txt -> ftp://host/a.txt: copy failed". The class stays the same.
+     */
+    pub fn context(mut self, context: impl fmt::Display) -> Self {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
+
+    /**
+     * This function is to get the exit code of the process.
+     */
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(self.kind as u8)
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure { kind: Kind::General, message, code: None }
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(message: &str) -> Self {
+        Failure::from(message.to_string())
+    }
+}
+
+impl From<FtpError> for Failure {
+    fn from(e: FtpError) -> Self {
+        Failure {
+            kind: Kind::from(&e),
+            message: e.to_string(),
+            code: e.code(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(text: &str) -> FtpError {
+        FtpError::Reply(FtpReply { code: text[..3].parse().unwrap(), text: text.to_string() })
+    }
+
+    #[test]
+    fn classifies_replies_by_code() {
+        assert_eq!(Failure::from(reply("421 Service not available")).kind, Kind::Connection);
+        assert_eq!(Failure::from(reply("530 Not logged in")).kind, Kind::Auth);
+        assert_eq!(Failure::from(reply("550 No such file")).kind, Kind::NotFound);
+        assert_eq!(Failure::from(reply("550 /etc/shadow: Permission denied.")).kind, Kind::Permission);
+        assert_eq!(Failure::from(reply("553 Could not create file")).kind, Kind::Permission);
+        assert_eq!(Failure::from(reply("500 Unknown command")).kind, Kind::General);
+        let failed = FtpError::TransferFailed { code: 451, reply: "451 Local error".to_string() };
+        assert_eq!(Failure::from(failed).kind, Kind::General);
+    }
+
+    #[test]
+    fn ignores_the_words_of_paths() {
+        let e = Failure::from(reply("550 dir/timed out.csv: No such file or directory"));
+        assert_eq!((e.kind, e.code), (Kind::NotFound, Some(550)));
+        let e = Failure::from(reply("553 verification failed.txt: Permission denied"));
+        assert_eq!(e.kind, Kind::Permission);
+        let e = Failure::from(reply("550 permission denied.txt: No such file"));
+        assert_eq!(e.kind, Kind::NotFound);
+    }
+
+    #[test]
+    fn classifies_by_variant() {
+        let auth = FtpError::Auth(FtpReply { code: 530, text: "530 Login incorrect".to_string() });
+        assert_eq!(Failure::from(auth).kind, Kind::Auth);
+        let cases = [
+            (FtpError::Connection("the data channel failed: Connection reset by peer (os error 104)".into()), Kind::Connection),
+            (FtpError::Timeout("can't read the server response: timed out".into()), Kind::Timeout),
+            (FtpError::LocalIo("No space left on device (os error 28)".into()), Kind::LocalIo),
+            (FtpError::Verify("size mismatch".into()), Kind::Verification),
+            (FtpError::Aborted("the transfer was aborted".into()), Kind::Interrupted),
+            (FtpError::Other("invalid SIZE reply: 213 x".into()), Kind::General),
+        ];
+        for (e, kind) in cases {
+            let e = Failure::from(e);
+            assert_eq!((e.kind, e.code), (kind, None), "{}", e);
+        }
+    }
+
+    #[test]
+    fn context_keeps_the_class() {
+        let e = Failure::from(reply("550 No such file")).context("a.txt -> b.txt: copy failed");
+        assert_eq!(e.message, "a.txt -> b.txt: copy failed: 550 No such file");
+        assert_eq!((e.kind, e.code), (Kind::NotFound, Some(550)));
+        assert_eq!(Failure::from("can't handle Ctrl-C").kind, Kind::General);
+        assert_eq!(Failure::usage("bad URL").exit_code(), ExitCode::from(2));
+    }
+}
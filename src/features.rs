@@ -0,0 +1,132 @@
+/**
+ * This struct is the capability set of a Ftp server, parsed from its reply to FEAT (RFC 2389).
+ * A server that doesn't support FEAT has an empty set, so every operation falls back
+ * to the commands of RFC 959.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Features {
+    /// Every feature line as sent by the server, e.g. "REST STREAM"
+    pub lines: Vec<String>,
+    /// The facts MLST and MLSD can return, lowercased and without '*', e.g. ["type", "size", "modify"]
+    pub mlst_facts: Vec<String>,
+    /// The algorithms HASH supports, uppercased and without '*', e.g. ["SHA-256", "MD5"]
+    pub hash: Vec<String>,
+    pub utf8: bool,
+    pub epsv: bool,
+    pub rest_stream: bool,
+    pub size: bool,
+    pub mdtm: bool,
+    pub mfmt: bool,
+    pub auth_tls: bool,
+}
+
+impl Features {
+    /**
+     * This function is to parse the feature lines of a FEAT reply.
+     * lines: The lines between the first and the last line of the reply.
+     */
+    pub fn parse(lines: &[String]) -> Self {
+        let mut features = Features {
+            lines: lines.to_vec(),
+            ..Default::default()
+        };
+
+        for line in lines {
+            let (name, args) = match line.split_once(' ') {
+                Some((name, args)) => (name.to_uppercase(), args.trim()),
+                None => (line.to_uppercase(), ""),
+            };
+
+            match name.as_str() {
+                "MLST" => {
+                    features.mlst_facts = split_list(args).map(|f| f.to_lowercase()).collect();
+                }
+                "HASH" => {
+                    features.hash = split_list(args).map(|f| f.to_uppercase()).collect();
+                }
+                "UTF8" => features.utf8 = true,
+                "EPSV" => features.epsv = true,
+                "REST" => features.rest_stream = args.eq_ignore_ascii_case("STREAM"),
+                "SIZE" => features.size = true,
+                "MDTM" => features.mdtm = true,
+                "MFMT" => features.mfmt = true,
+                "AUTH" => {
+                    features.auth_tls = split_list(args).any(|m| m.eq_ignore_ascii_case("TLS"));
+                }
+                _ => {}
+            }
+        }
+
+        features
+    }
+
+    /**
+     * This function is to tell whether the server advertises a command, ignoring case.
+     * command: The command, e.g. "XMD5".
+     */
+    pub fn has(&self, command: &str) -> bool {
+        self.lines
+            .iter()
+            .any(|line| line.split(' ').next().unwrap_or("").eq_ignore_ascii_case(command))
+    }
+}
+
+/**
+ * This function is to split a ';' separated feature argument, dropping the '*' that
+ * marks the entries currently enabled, e.g. "type*;size*;perm;" -> type, size, perm.
+ */
+fn split_list(args: &str) -> impl Iterator<Item = &str> {
+    args.split(';')
+        .map(|entry| entry.trim().trim_end_matches('*'))
+        .filter(|entry| !entry.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Features {
+        Features::parse(&lines.iter().map(|l| l.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_features() {
+        let features = parse(&[
+            "EPSV",
+            "MDTM",
+            "MFMT",
+            "rest stream",
+            "SIZE",
+            "UTF8",
+            "AUTH TLS;TLS-C",
+            "MLST Type*;Size*;Modify*;Perm;",
+            "HASH SHA-1;sha-256*;MD5",
+        ]);
+        assert!(features.epsv && features.mdtm && features.mfmt && features.rest_stream);
+        assert!(features.size && features.utf8 && features.auth_tls);
+        assert_eq!(features.mlst_facts, ["type", "size", "modify", "perm"]);
+        assert_eq!(features.hash, ["SHA-1", "SHA-256", "MD5"]);
+    }
+
+    #[test]
+    fn empty_set_without_feat() {
+        let features = parse(&[]);
+        assert_eq!(features, Features::default());
+        assert!(!features.has("SIZE"));
+    }
+
+    #[test]
+    fn rest_without_stream() {
+        assert!(!parse(&["REST"]).rest_stream);
+        assert!(!parse(&["AUTH SSL"]).auth_tls);
+    }
+
+    #[test]
+    fn has_command() {
+        let features = parse(&["XMD5", "XCRC", "MLST type*;"]);
+        assert!(features.has("xmd5"));
+        assert!(features.has("MLST"));
+        assert!(!features.has("XSHA256"));
+        assert!(!features.has("XMD"));
+    }
+}
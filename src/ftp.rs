@@ -1,46 +1,229 @@
 use std::{
-    fs::File,
-    io::{BufReader, Read, Write},
-    net::TcpStream,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpStream, ToSocketAddrs},
+    path::Path,
+    time::{Duration, SystemTime},
 };
 
+use encoding_rs::{Encoding, UTF_8};
 use regex::Regex;
+use socket2::{SockRef, TcpKeepalive};
+
+use crate::{
+    abort::AbortHandle,
+    ascii::{ToCrlf, ToLf},
+    features::Features,
+    keepalive::KeepAlive,
+    logger::{self, Level},
+    stat::{self, FileStat},
+};
+
+// The Telnet commands sent ahead of ABOR: Interpret As Command, Interrupt Process and Data Mark
+const TELNET_IAC: u8 = 0xFF;
+const TELNET_IP: u8 = 0xF4;
+const TELNET_DM: u8 = 0xF2;
+
+// How long a connection is idle before TCP keepalive starts probing it
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
 
 // Allocate a static memory for PORT_REGEX
 lazy_static! {
     static ref PORT_REGEX: Regex = Regex::new(r"\((\d+),(\d+),(\d+),(\d+),(\d+),(\d+)\)").unwrap();
+    static ref EPSV_REGEX: Regex = Regex::new(r"\(\|\|\|(\d+)\|\)").unwrap();
+}
+
+/**
+ * This is the error of FtpStream. Its variant is decided where the error happens, so the
+ * caller can tell what failed (the connection, the login, a command the server rejected,
+ * a local file ...) without looking at the message.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FtpError {
+    /// Can't connect to the server, or the control or data channel is lost
+    Connection(String),
+    /// The server didn't answer, or didn't take the data, within the timeout
+    Timeout(String),
+    /// The server rejected the username, the password or the account, e.g. 530
+    Auth(FtpReply),
+    /// The server rejected a command, e.g. 550 and "550 No such file"
+    Reply(FtpReply),
+    /// The data channel was used, but the server didn't reply 2xx (e.g. 226 or 250) afterwards.
+    /// It carries the reply code and the full reply, e.g. 552 and "552 Quota exceeded"
+    TransferFailed { code: u16, reply: String },
+    /// A local file can't be read or written
+    LocalIo(String),
+    /// The copy doesn't match the source
+    Verify(String),
+    /// The transfer was aborted with an AbortHandle
+    Aborted(String),
+    /// Any other error, described by its message
+    Other(String),
+}
+
+impl FtpError {
+    /**
+     * This function is to create the error of a reply the server sent instead of the
+     * expected one. The reply always starts with its code, as read_message reads it.
+     */
+    fn reply(res: String) -> Self {
+        FtpError::Reply(FtpReply {
+            code: res.get(..3).and_then(|code| code.parse().ok()).unwrap_or(0),
+            text: res,
+        })
+    }
+
+    /**
+     * This function is to create the error of a login the server rejected.
+     */
+    fn auth(res: String) -> Self {
+        match FtpError::reply(res) {
+            FtpError::Reply(reply) => FtpError::Auth(reply),
+            e => e,
+        }
+    }
+
+    /**
+     * This function is to create the error of a failed read or write on the control or data
+     * channel, which is a timeout if the timeout of the connection has passed.
+     * context: What was being done, e.g. "can't read the server response".
+     */
+    fn channel(context: &str, e: io::Error) -> Self {
+        match e.kind() {
+            // A timeout is reported as WouldBlock on Unix and TimedOut on Windows
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => FtpError::Timeout(format!("{}: timed out", context)),
+            _ => FtpError::Connection(format!("{}: {}", context, e)),
+        }
+    }
+
+    /**
+     * This function is to get the code of the server reply the error carries, if any.
+     */
+    pub fn code(&self) -> Option<u16> {
+        match self {
+            FtpError::Auth(reply) | FtpError::Reply(reply) => Some(reply.code),
+            FtpError::TransferFailed { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FtpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FtpError::Auth(reply) | FtpError::Reply(reply) => write!(f, "{}", reply),
+            FtpError::TransferFailed { reply, .. } => write!(f, "transfer failed: {}", reply),
+            FtpError::Connection(msg)
+            | FtpError::Timeout(msg)
+            | FtpError::LocalIo(msg)
+            | FtpError::Verify(msg)
+            | FtpError::Aborted(msg)
+            | FtpError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<String> for FtpError {
+    fn from(msg: String) -> Self {
+        FtpError::Other(msg)
+    }
+}
+
+impl From<FtpError> for String {
+    fn from(e: FtpError) -> Self {
+        e.to_string()
+    }
+}
+
+/**
+ * This struct is a reply of the Ftp server, e.g. to a command sent with raw_command.
+ * The text is the whole reply with its code, the lines of a multi-line reply joined with '\n'.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FtpReply {
+    pub code: u16,
+    pub text: String,
+}
+
+impl FtpReply {
+    /**
+     * This function is to tell whether the reply is a failure, i.e. its code is 4xx or 5xx.
+     */
+    pub fn is_failure(&self) -> bool {
+        self.code >= 400
+    }
+}
+
+impl fmt::Display for FtpReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/**
+ * This enum is the representation type files are transferred with (TYPE).
+ */
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TransferType {
+    /// TYPE A, line endings are CRLF on the wire and LF in the local file
+    Ascii,
+    /// TYPE I, the bytes are transferred unchanged
+    Binary,
 }
 
 /**
  * This is the FtpStream struct. It contains the control channel to the Ftp server
  * which is wrapped in BufReader, initial message send by the Ftp server when the control
- * channel is connected successfully, and a bool to indecate whether it should print the
- * server message or not. 
+ * channel is connected successfully, the id of the connection in the log, the representation
+ * type the server is currently set to, the features of the server, the encoding of the control
+ * channel and the listings, the timeout of the control and data channels, the current
+ * directory on the server, how often NOOP is sent during a transfer, the handle its
+ * transfers are aborted with, and whether the control channel is lost.
  */
 pub struct FtpStream {
     tcp_control: BufReader<TcpStream>,
     init_messege: String,
-    verbose_mode: bool
+    id: u64,
+    transfer_type: TransferType,
+    features: Features,
+    encoding: &'static Encoding,
+    timeout: Option<Duration>,
+    current_dir: Option<String>,
+    keepalive: Option<Duration>,
+    abort: AbortHandle,
+    lost: bool
 }
 
 // All the functions implemented for FtpStream
 impl FtpStream {
     /**
      * This function is to initialize a FtpStream with given host and port number.
-     * hostname: The hostname of the Ftp server.
+     * hostname: The hostname or the IP address (IPv4 or IPv6) of the Ftp server.
      * port_num: The port number to use.
-     * v: To print server message or not.
-     * Return Ok(FtpStream) if no error occurs, otherwise Err(String)
+     * timeout: How long to wait for connecting and for every read and write on the control
+     * and data channels. None waits forever.
+     * Return Ok(FtpStream) if no error occurs, otherwise Err(FtpError)
      */
-    pub fn new(hostname: &str, port_num: &str, v:bool) -> Result<Self, String> {
-        TcpStream::connect(format!("{}:{}", hostname, port_num))
-            .map_err(|e| format!("connection failed: {}", e))
+    pub fn new(hostname: &str, port_num: u16, timeout: Option<Duration>) -> Result<Self, FtpError> {
+        let id = logger::next_id();
+        logger::log(Level::Info, id, format_args!("connecting to {}:{}", hostname, port_num));
+        open((hostname, port_num), timeout)
+            .map_err(|e| FtpError::channel("connection failed", e))
             .and_then(|stream| {
                 // Create the FtpStream instance
                 let mut ftp = FtpStream {
                     tcp_control: BufReader::new(stream),
                     init_messege: String::new(),
-                    verbose_mode: v
+                    id,
+                    transfer_type: TransferType::Binary,
+                    features: Features::default(),
+                    encoding: UTF_8,
+                    timeout,
+                    current_dir: None,
+                    keepalive: None,
+                    abort: AbortHandle::default(),
+                    lost: false
                 };
 
                 // Read the initial message
@@ -49,7 +232,7 @@ impl FtpStream {
                 // Check if the initial connection is successful
                 match &res[0..1] {
                     "2" => {},
-                    _ => {return Err(res)}
+                    _ => {return Err(FtpError::reply(res))}
                 }
 
                 ftp.init_messege = res;
@@ -59,119 +242,357 @@ impl FtpStream {
 
     /**
      * This function is to read the message sent by Ftp server.
+     * A multi-line reply ("123-First line" ... "123 Last line") is read as a whole
+     * and its lines are joined with '\n'.
      * This function can only be used inside the modeul.
      * Return Ok(String) with server message if no error occurs, 
-     * otherwise Err(String) with error message
+     * otherwise Err(FtpError) with error message
      */
-    fn read_message(&mut self) -> Result<String, String> {
-        // Buffer array
-        let mut buf = [0u8; 16];
+    fn read_message(&mut self) -> Result<String, FtpError> {
         // Where the server message goes
         let mut res = String::new();
 
-        // Continuously read from the server until \r\n appears
+        // Continuously read lines from the server until the last line of the reply
         loop {
-            match self
-                .tcp_control
-                .read(&mut buf)
-                .map_err(|e| format!("can't read the server response: {}", e))
-            {
-                Ok(_) => {}
-                Err(e) => {
-                    return Err(e);
-                }
+            let mut line: Vec<u8> = vec![];
+            let read = match self.tcp_control.read_until(b'\n', &mut line) {
+                Ok(0) => Err(FtpError::Connection("the server closed the control channel".to_string())),
+                Ok(_) => Ok(()),
+                Err(e) => Err(FtpError::channel("can't read the server response", e)),
+            };
+            // A reply that comes late would be taken for the reply to the next command
+            if let Err(e) = &read {
+                logger::log(Level::Error, self.id, format_args!("{}", e));
+                self.lost = true;
             }
+            read?;
 
-            // Translate message from utf8 to string
-            let tmp = String::from_utf8_lossy(&mut buf).to_string();
+            // Translate message from the encoding of the control channel to string
+            let line = self.decode(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
 
-            match tmp.find("\r\n") {
-                Some(_) => {
-                    let (last_part, _) = tmp.split_at(tmp.find("\r\n").unwrap());
-                    res.push_str(last_part);
-                    break;
-                }
-                None => {
-                    res.push_str(tmp.as_str());
-                }
+            if !res.is_empty() {
+                res.push('\n');
+            }
+            res.push_str(line);
+
+            // The reply ends with a line that starts with the reply code followed by a space
+            let code = &res.as_bytes()[..res.len().min(3)];
+            if line.len() >= 3 && line.as_bytes()[..3] == *code && line.as_bytes().get(3) != Some(&b'-') {
+                break;
             }
         }
 
-        // Print the message to stdout if it's in verbose mode
-        if self.verbose_mode {
-            println!("{}", &res);
+        // A failure is logged at a lower level, so it's seen without the whole transcript
+        let level = match &res[..1] {
+            "4" | "5" => Level::Warn,
+            _ => Level::Debug,
+        };
+        for line in res.lines() {
+            logger::log(level, self.id, format_args!("< {}", line));
         }
 
         Ok(res)
     }
 
     /**
-     * This function is to send the command to the Ftp server.
+     * This function is to build a command line from a command and its argument, and send
+     * it to the Ftp server. Every command is sent through this function, as it makes sure
+     * an argument (a path, a username, a password ...) can't end the command line early:
+     * an argument containing CR, LF or NUL is rejected, otherwise it could inject commands.
      * This function can only be used inside the module.
-     * msg: The command that needs to be sent to the Ftp server.
+     * command: The command, e.g. "RETR".
+     * arg: The argument of the command, or "" if it has none.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise Err(FtpError) with error message
+     */
+    fn send_command(&mut self, command: &str, arg: &str) -> Result<String, FtpError> {
+        check_command(command, arg)?;
+
+        match arg.is_empty() {
+            true => logger::log(Level::Debug, self.id, format_args!("> {}", command)),
+            false => logger::log(Level::Debug, self.id, format_args!("> {} {}", command, logger::masked(command, arg))),
+        }
+        match arg.is_empty() {
+            true => self.send_message(format!("{}\r\n", command)),
+            false => self.send_message(format!("{} {}\r\n", command, arg)),
+        }
+    }
+
+    /**
+     * This function is to send the command line to the Ftp server. The control channel is
+     * a Telnet connection (RFC 959), so a 0xFF byte, which is the Telnet IAC, is doubled.
+     * This function can only be used inside the module, use send_command to build the line.
+     * msg: The command line that needs to be sent to the Ftp server, ending with CRLF.
      * Return Ok(String) with success message if no error occurs, 
-     * otherwise Err(String) with error message
+     * otherwise Err(FtpError) with error message
      */
-    fn send_message(&mut self, msg: String) -> Result<String, String> {
-        match self.tcp_control.get_mut().write(msg.as_bytes()) {
+    fn send_message(&mut self, msg: String) -> Result<String, FtpError> {
+        let line = encode_line(self.encoding, &msg)?;
+        match self.tcp_control.get_mut().write_all(&line).map(|_| line.len()) {
             Ok(len) => Ok(format!("Successfully wrote {} bytes", len)),
-            Err(e) => Err(format!("Failed to write to the server: {}", e)),
+            Err(e) => {
+                self.lost = true;
+                Err(FtpError::channel("Failed to write to the server", e))
+            }
+        }
+    }
+
+    /**
+     * This function is to tell whether the control channel is lost: it's closed, or a read
+     * or a write failed or timed out. The FtpStream can't be used any more, a new one has
+     * to be connected.
+     */
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    /**
+     * This function is to set the encoding of the paths in the commands, the server messages
+     * and the listings. It's UTF-8 by default, which configure announces to the server with
+     * OPTS UTF8 ON if the server supports it.
+     * encoding: The encoding, e.g. encoding_rs::SHIFT_JIS.
+     */
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        self.encoding = encoding;
+    }
+
+    /**
+     * This function is to set how often NOOP is sent over the control channel during a data
+     * transfer, so a firewall doesn't drop the control channel while it's idle and the reply
+     * to the transfer is lost. None, the default, sends no NOOP.
+     * interval: The time between two NOOPs.
+     */
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) {
+        self.keepalive = interval;
+    }
+
+    /**
+     * This function is to get the handle that aborts the transfers of this FtpStream from
+     * another thread. The FtpStream stays usable after an abort.
+     */
+    #[allow(unused)]
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.abort.clone()
+    }
+
+    /**
+     * This function is to share an AbortHandle, so a single abort stops the transfers of
+     * several FtpStreams, e.g. the connections of a parallel transfer.
+     */
+    pub fn set_abort_handle(&mut self, handle: AbortHandle) {
+        self.abort = handle;
+    }
+
+    /**
+     * This function is to abort the transfer in progress once its data channel is closed
+     * (RFC 959 section 4.1.3): the Telnet IP and Synch are sent ahead of ABOR, so a server
+     * that is busy with the transfer reads it at once. The server replies 426 and 226, or
+     * only 226 if the transfer was over, so a NOOP is sent after ABOR and every reply up to
+     * the one to the NOOP is read, which leaves the control channel in sync. A reply that
+     * isn't one to the transfer or to ABOR is one to a NOOP, whatever its code.
+     * This function can only be used inside the module.
+     * noops: The number of NOOPs sent during the transfer, whose replies are still pending.
+     * Return FtpError::Aborted with the error message, with the replies of the server.
+     */
+    fn abort_transfer(&mut self, noops: usize) -> FtpError {
+        logger::log(Level::Info, self.id, format_args!("aborting the transfer"));
+        let replies = self.send_abort().and_then(|_| {
+            let mut replies = vec![];
+            let mut pending = noops + 1;
+            while pending > 0 {
+                let res = self.read_message()?;
+                match res.get(..3).unwrap_or(&res) {
+                    // The server is closing the connection, no other reply comes
+                    "421" => {
+                        replies.push(res);
+                        break;
+                    }
+                    "225" | "226" | "250" | "425" | "426" | "451" | "551" | "552" => replies.push(res),
+                    _ => pending -= 1,
+                }
+            }
+            Ok(replies)
+        });
+
+        match replies {
+            Ok(replies) => FtpError::Aborted(format!("the transfer was aborted: {}", replies.join(", "))),
+            Err(e) => FtpError::Aborted(format!("the transfer was aborted, but the control channel is lost: {}", e)),
+        }
+    }
+
+    /**
+     * This function is to send the Telnet IP and Synch, then ABOR and NOOP. The Synch is an
+     * IAC sent as TCP urgent data followed by DM, it's written directly as send_message
+     * would double the IAC.
+     * This function can only be used inside the module.
+     * Return Ok(()) if no error occurs, otherwise, Err(FtpError) with error message.
+     */
+    fn send_abort(&mut self) -> Result<(), FtpError> {
+        logger::log(Level::Debug, self.id, format_args!("> IP, Synch"));
+        let mut control = self.tcp_control.get_ref();
+        control
+            .write_all(&[TELNET_IAC, TELNET_IP])
+            .and_then(|_| SockRef::from(control).send_out_of_band(&[TELNET_IAC]))
+            .and_then(|_| control.write_all(&[TELNET_DM]))
+            .map_err(|e| {
+                self.lost = true;
+                FtpError::channel("Failed to write to the server", e)
+            })?;
+        self.send_command("ABOR", "")?;
+        self.send_command("NOOP", "")?;
+        Ok(())
+    }
+
+    /**
+     * This function is to send NOOP, so an idle session isn't dropped by a firewall or by
+     * the idle timeout of the server.
+     * Return Ok(String) with the reply if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    #[allow(unused)]
+    pub fn noop(&mut self) -> Result<String, FtpError> {
+        self.send_command("NOOP", "")?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => Ok(res),
+            _ => Err(FtpError::reply(res)),
+        }
+    }
+
+    /**
+     * This function is to read the reply to a data transfer along with the replies to the
+     * NOOPs sent during it. A server answers a NOOP either during the transfer or once it's
+     * over, so the replies 200 before the reply to the transfer are taken as the ones to NOOP.
+     * This function can only be used inside the module.
+     * noops: The number of NOOPs sent during the transfer.
+     * Return Ok(String) with the reply to the transfer if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    fn read_transfer_reply(&mut self, mut noops: usize) -> Result<String, FtpError> {
+        let mut transfer = None;
+        while transfer.is_none() || noops > 0 {
+            let res = self.read_message()?;
+            if noops > 0 && (transfer.is_some() || res.starts_with("200")) {
+                noops -= 1;
+            } else {
+                transfer = Some(res);
+            }
         }
+        Ok(transfer.unwrap())
+    }
+
+    /**
+     * This function is to translate the bytes of a server message or listing to string,
+     * with the encoding of the connection.
+     */
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        self.encoding.decode_without_bom_handling(bytes).0.into_owned()
     }
 
     /**
      * This function is to login the Ftp server with given username and password.
      * username: The username of the cilent.
-     * password: The passwrod of the client.
+     * password: Gives the passwrod of the client. It's only called if the server asks for
+     * a password (3xx), so the password can be prompted for only when it's needed.
      * Return Ok(String) with success message if no error occurs,
-     * otherwise, Err(String)
+     * otherwise, Err(FtpError), which is FtpError::Auth if the server rejects the login.
      */
-    pub fn login(&mut self, username: &str, password: &str) -> Result<String, String> {
-        self.send_message(format!("USER {}\r\n", username))?;
+    pub fn login(
+        &mut self,
+        username: &str,
+        password: impl FnOnce() -> Result<String, String>,
+    ) -> Result<String, FtpError> {
+        self.send_command("USER", username)?;
         // Read the server's response
         let res = self.read_message()?;
         // Check if the server responds the correct code
         match &res[0..1] {
-            "2"|"3" => {},
-            _ => {return Err(res)}
+            "2" => {return Ok("Successfully logged in".to_string())},
+            "3" => {},
+            _ => {return Err(FtpError::auth(res))}
         }
 
         // Send password to the server
-        self.send_message(
-            format!("PASS {}\r\n", password),
-        )?;
+        self.send_command("PASS", &password()?)?;
         let res = self.read_message()?;
         match &res[0..1] {
             "2" => {},
-            _ => {return Err(res)}
+            _ => {return Err(FtpError::auth(res))}
+        }
+
+        Ok("Successfully logged in".to_string())
+    }
+
+    /**
+     * This function is to configure the session after login. The features of the server
+     * are negotiated with FEAT and cached (see features), UTF-8 paths are turned on if both
+     * the server and the encoding support them, and the server is configured to Binary mode.
+     * Stream mode and File-Oriented mode are requested as well, but as they are the defaults
+     * of RFC 959, a server rejecting them is not an error.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise, Err(FtpError)
+     */
+    pub fn configure(&mut self) -> Result<String, FtpError> {
+        // A server without FEAT has no features beyond RFC 959
+        self.features = Features::parse(&self.feat().unwrap_or_default());
+
+        // Some servers only send and accept UTF-8 paths after this, it's fine if it's rejected
+        if self.features.utf8 && self.encoding == UTF_8 {
+            let _ = self.opts("UTF8 ON");
         }
 
         // Configure the server to Binary mode
-        self.send_message("TYPE I\r\n".to_string())?;
+        self.send_command("TYPE", "I")?;
         let res = self.read_message()?;
         match &res[0..1] {
             "2" => {},
-            _ => {return Err(res)}
+            _ => {return Err(FtpError::reply(res))}
         }
+        self.transfer_type = TransferType::Binary;
 
+        // Configure the server to Stream mode and File-Oriented mode
+        self.send_command("MODE", "S")?;
+        self.read_message()?;
+        self.send_command("STRU", "F")?;
+        self.read_message()?;
 
-        // Configure the server to Stream mode
-        self.send_message("MODE S\r\n".to_string())?;
-        let res = self.read_message()?;
-        match &res[0..1] {
-            "2" => {},
-            _ => {return Err(res)}
+        Ok("Successfully configured the session".to_string())
+    }
+
+    /**
+     * This function is to get the features of the server negotiated by configure.
+     */
+    pub fn features(&self) -> &Features {
+        &self.features
+    }
+
+    /**
+     * This function is to set the representation type of the following transfers.
+     * Nothing is sent if the server is already set to the given type.
+     * transfer_type: TransferType::Ascii for TYPE A, TransferType::Binary for TYPE I.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn set_type(&mut self, transfer_type: TransferType) -> Result<String, FtpError> {
+        if self.transfer_type == transfer_type {
+            return Ok(format!("Already in {:?} mode", transfer_type));
         }
 
-        // Configure the server to File-Oriented mode
-        self.send_message("STRU F\r\n".to_string())?;
+        self.send_command("TYPE", match transfer_type {
+            TransferType::Ascii => "A",
+            TransferType::Binary => "I",
+        })?;
         let res = self.read_message()?;
+
         match &res[0..1] {
-            "2" => {},
-            _ => {return Err(res)}
+            "2" => {
+                self.transfer_type = transfer_type;
+                Ok(res)
+            },
+            _ => Err(FtpError::reply(res)),
         }
-
-        Ok("Successfully logged in".to_string())
     }
 
     /**
@@ -179,54 +600,106 @@ impl FtpStream {
      * This functon can only be used inside the module.
      * stream: The TcpStream of the data channel.
      * Return Ok(Vec<u8>) which contains the file data if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message, which is FtpError::TransferFailed
+     * if the server doesn't confirm the transfer.
      */
-    fn read_data_channel(&mut self, mut stream: TcpStream) -> Result<Vec<u8>, String> {
+    fn read_data_channel(&mut self, stream: TcpStream) -> Result<Vec<u8>, FtpError> {
         let mut buf: Vec<u8> = vec![];
-        match stream.read_to_end(&mut buf).map_err(|e| format!("{}", e)) {
-            Ok(_) => {
-                self.read_message()?;
-                Ok(buf)
-            }
-            Err(e) => {
-                self.read_message()?;
-                Err(e)
+        self.copy_data_channel(stream, &mut buf)?;
+        Ok(buf)
+    }
+
+    /**
+     * This function is to receive data from data channel and write it to `out` as it arrives.
+     * This functon can only be used inside the module.
+     * stream: The TcpStream of the data channel.
+     * out: Where the data is written to.
+     * Return Ok(u64) with the number of bytes received if no error occurs,
+     * otherwise, Err(FtpError) with error message, which is FtpError::TransferFailed
+     * if the server doesn't confirm the transfer.
+     */
+    fn copy_data_channel(&mut self, stream: TcpStream, out: &mut impl Write) -> Result<u64, FtpError> {
+        let watch = self.abort.watch(&stream);
+        let mut stream = KeepAlive::new(stream, self.tcp_control.get_ref(), self.keepalive, self.id);
+        let copied = copy_data(&mut stream, out, false);
+        let (noops, lost) = (stream.sent, stream.lost.take());
+        drop(stream);
+        drop(watch);
+        self.log_data_closed(copied.as_ref().ok(), "received");
+        self.check_keepalive(lost)?;
+        if self.abort.is_aborted() {
+            return Err(self.abort_transfer(noops));
+        }
+
+        // The transfer is only complete if the server confirms it, the reply is read
+        // even if the copy failed so the control channel stays in sync
+        let res = self.read_transfer_reply(noops)?;
+        let copied = copied?;
+        check_transfer_reply(res)?;
+        Ok(copied)
+    }
+
+    /**
+     * This function is to check whether a NOOP sent during a transfer failed, in which case
+     * the control channel is lost and no reply can be read.
+     * This function can only be used inside the module.
+     * lost: The error of the NOOP, if any.
+     * Return Ok(()) if no NOOP failed, otherwise, Err(FtpError) with error message.
+     */
+    fn check_keepalive(&mut self, lost: Option<io::Error>) -> Result<(), FtpError> {
+        match lost {
+            Some(e) => {
+                self.lost = true;
+                Err(FtpError::channel("can't send NOOP on the control channel", e))
             }
+            None => Ok(()),
         }
     }
 
     /**
-     * This function is to write data to the data channel.
+     * This function is to write data to the data channel. In ASCII mode, the line endings
+     * are converted to CRLF as the data is written.
      * This function can only be used inside the module.
      * stream: The TcpStream of the data channel.
-     * data: The data to be written to the datachannel.
+     * data: Where the data to be written to the datachannel is read from.
      * Return Ok(String) with success message if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message, which is FtpError::TransferFailed
+     * if the server doesn't confirm the transfer.
      */
     fn write_data_channel(
         &mut self,
-        mut stream: TcpStream,
-        data: Vec<u8>,
-    ) -> Result<String, String> {
-        match stream.write(&data).map_err(|e|format!("{}", e)) {
-            Ok(len) => {
-                // Shutdown data channel to notify the server that the transaction is completed
-                stream.shutdown(std::net::Shutdown::Both).map_err(|e| format!("{}", e))?;
-                self.read_message()?;
-                Ok(format!(
-                    "Successfully wrote {} bytes to the data channel",
-                    len
-                ))
-            },
-            Err(e) => {
-                // Shutdown data channel to notify the server that the transaction is completed
-                stream.shutdown(std::net::Shutdown::Both).map_err(|e| format!("{}", e))?;
-                self.read_message()?;
-                Err(e)
-            }
+        stream: TcpStream,
+        mut data: impl Read,
+    ) -> Result<String, FtpError> {
+        let watch = self.abort.watch(&stream);
+        let mut stream = KeepAlive::new(stream, self.tcp_control.get_ref(), self.keepalive, self.id);
+        let written = match self.transfer_type {
+            TransferType::Ascii => copy_data(&mut data, &mut ToCrlf::new(&mut stream), true),
+            TransferType::Binary => copy_data(&mut data, &mut stream, true),
+        };
+        let (noops, lost) = (stream.sent, stream.lost.take());
+        drop(watch);
+        if lost.is_some() || self.abort.is_aborted() {
+            drop(stream);
+            self.log_data_closed(None, "sent");
+            self.check_keepalive(lost)?;
+            return Err(self.abort_transfer(noops));
         }
-        
-        
+        // Shutdown data channel to notify the server that the transaction is completed
+        stream
+            .get_ref()
+            .shutdown(std::net::Shutdown::Both)
+            .map_err(|e| FtpError::channel("can't close the data channel", e))?;
+        drop(stream);
+        self.log_data_closed(written.as_ref().ok(), "sent");
+
+        // The transfer is only complete if the server confirms it
+        let res = self.read_transfer_reply(noops)?;
+        check_transfer_reply(res)?;
+        Ok(format!(
+            "Successfully wrote {} bytes to the data channel",
+            written?
+        ))
     }
 
     /**
@@ -234,61 +707,182 @@ impl FtpStream {
      * directory would be print to the stdout.
      * path: The path of the directory that ls command would be executed.
      * Return Ok(String) with success message if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message.
      */
-    pub fn list(&mut self, path: &str) -> Result<String, String> {
+    pub fn list(&mut self, path: &str) -> Result<String, FtpError> {
+        println!("{}", self.list_raw(path)?);
+        Ok(format!(
+            "successfully read {} directory listing from the server",
+            path
+        ))
+    }
+
+    /**
+     * This function is to get the listing of LIST, decoded with the encoding.
+     * This function can only be used inside the module.
+     * path: The path of the directory or the file.
+     * Return Ok(String) with the listing if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    fn list_raw(&mut self, path: &str) -> Result<String, FtpError> {
         // Request data channel
         match self.pasv() {
             Ok(stream) => {
-                self.send_message(format!("LIST {}\r\n", path))?;
+                self.send_command("LIST", path)?;
                 let res = self.read_message()?;
 
                 // Check if server response is correct for moving on to next step
                 match &res[0..1] {
                     "1" => {},
-                    _ => {return Err(res)}
+                    _ => {return Err(FtpError::reply(res))}
                 }
 
                 let buf: Vec<u8> = self.read_data_channel(stream)?;
-                println!("{}", String::from_utf8_lossy(&buf));
-                Ok(format!(
-                    "successfully read {} directory listing from the server",
-                    path
-                ))
+                Ok(self.decode(&buf))
             }
             Err(e) => Err(e),
         }
     }
 
+    /**
+     * This function is to get the entries of a directory on the Ftp server. MLSD (RFC 3659)
+     * is used if the server supports MLST, otherwise the lines of LIST that can be parsed.
+     * The entries for the directory itself and its parent are left out.
+     * path: The path of the directory.
+     * Return Ok(Vec<FileStat>) with the entries if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn list_entries(&mut self, path: &str) -> Result<Vec<FileStat>, FtpError> {
+        if !self.features.has("MLST") {
+            return Ok(self
+                .list_raw(path)?
+                .lines()
+                .filter_map(FileStat::parse_list)
+                .filter(|stat| stat.name != "." && stat.name != "..")
+                .collect());
+        }
+
+        let stream = self.pasv()?;
+        self.send_command("MLSD", path)?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "1" => {},
+            _ => {return Err(FtpError::reply(res))}
+        }
+
+        let buf = self.read_data_channel(stream)?;
+        Ok(self
+            .decode(&buf)
+            .lines()
+            .filter_map(FileStat::parse_mlst)
+            .filter(|stat| !matches!(stat.kind.as_deref(), Some("cdir") | Some("pdir")))
+            .collect())
+    }
+
     /**
      * This function is to perform mkdir command on the Ftp server.
      * path: The path of the directory to be created.
      * Return Ok(String) with success message if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message.
      */
-    pub fn mkd(&mut self, path: &str) -> Result<String, String> {
-        self.send_message(format!("MKD {}\r\n", path))?;
+    pub fn mkd(&mut self, path: &str) -> Result<String, FtpError> {
+        self.send_command("MKD", path)?;
         let res = self.read_message()?;
 
         match &res[0..1] {
             "2" => Ok(res),
-            _ => Err(res),
+            _ => Err(FtpError::reply(res)),
+        }
+    }
+
+    /**
+     * This function is to get the current directory on the server with PWD, which is
+     * remembered as well (see current_dir).
+     * Return Ok(String) with the directory if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    #[allow(unused)]
+    pub fn pwd(&mut self) -> Result<String, FtpError> {
+        self.send_command("PWD", "")?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => {},
+            _ => {return Err(FtpError::reply(res))}
+        }
+
+        let dir = parse_quoted_path(&res).ok_or_else(|| FtpError::Other(format!("Didn't capture the directory {}", res)))?;
+        self.current_dir = Some(dir.clone());
+        Ok(dir)
+    }
+
+    /**
+     * This function is to change the current directory on the server with CWD.
+     * path: The directory to change to, absolute or relative to the current directory.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn cwd(&mut self, path: &str) -> Result<String, FtpError> {
+        self.send_command("CWD", path)?;
+        self.change_dir()
+    }
+
+    /**
+     * This function is to change the current directory on the server to its parent with CDUP.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    #[allow(unused)]
+    pub fn cdup(&mut self) -> Result<String, FtpError> {
+        self.send_command("CDUP", "")?;
+        self.change_dir()
+    }
+
+    /**
+     * This function is to read the reply of CWD or CDUP, and ask the server for the new
+     * current directory, as the path sent may be relative or contain "..".
+     * This function can only be used inside the module.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    fn change_dir(&mut self) -> Result<String, FtpError> {
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => {},
+            _ => {return Err(FtpError::reply(res))}
+        }
+
+        // The directory is unknown if the server can't tell it
+        if self.pwd().is_err() {
+            self.current_dir = None;
         }
+        Ok(res)
+    }
+
+    /**
+     * This function is to get the current directory on the server, as of the last pwd,
+     * cwd or cdup. It's None before any of them, or if the server didn't tell it.
+     */
+    #[allow(unused)]
+    pub fn current_dir(&self) -> Option<&str> {
+        self.current_dir.as_deref()
     }
 
     /**
      * This function is to perform rmdir command on the Ftp server.
      * path: The path of the directory to be removed.
      * Return Ok(String) with success message if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message.
      */
-    pub fn rmd(&mut self, path: &str) -> Result<String, String> {
-        self.send_message(format!("RMD {}\r\n", path))?;
+    pub fn rmd(&mut self, path: &str) -> Result<String, FtpError> {
+        self.send_command("RMD", path)?;
         let res = self.read_message()?;
 
         match &res[0..1] {
             "2" => Ok(res),
-            _ => Err(res),
+            _ => Err(FtpError::reply(res)),
         }
     }
 
@@ -296,87 +890,554 @@ impl FtpStream {
      * This function is to perform rm command on the Ftp server.
      * path: The path of the file to be removed.
      * Return Ok(String) with success message if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message.
      */
-    pub fn dele(&mut self, path: &str) -> Result<String, String> {
-        self.send_message(format!("DELE {}\r\n", path))?;
+    pub fn dele(&mut self, path: &str) -> Result<String, FtpError> {
+        self.send_command("DELE", path)?;
         let res = self.read_message()?;
 
         match &res[0..1] {
             "2" => Ok(res),
-            _ => Err(res),
+            _ => Err(FtpError::reply(res)),
         }
     }
 
     /**
-     * This function is to transfer a given file to the Ftp server.
-     * file_path: The path of the file in the local storage.
-     * server_path: The path of the file that the file would be stored at after the execution.
+     * This function is to rename a file or directory on the Ftp server.
+     * from: The current path of the file.
+     * to: The new path of the file.
      * Return Ok(String) with success message if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message.
      */
-    pub fn stor(&mut self, file_path: &str, server_path: &str) -> Result<String, String> {
-        // Read the local file
-        let mut f = File::open(file_path)
-            .map_err(|_e| format!("can't find the local file wiht given path {}", file_path))?;
-        let mut buf: Vec<u8> = vec![];
-        // Write the file data to the u8 vector
-        f.read_to_end(&mut buf).map_err(|e| format!("{}", e))?;
-        // Request the data channel
-        let stream = self.pasv()?;
-        self.send_message(format!("STOR {}\r\n", server_path))?;
-        
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<String, FtpError> {
+        self.send_command("RNFR", from)?;
         let res = self.read_message()?;
-        // Check if it's legit to send data
+        // The server waits for RNTO if the file exists
         match &res[0..1] {
-            "1" => {},
-            _ => {return Err(res)}
+            "3" => {},
+            _ => {return Err(FtpError::reply(res))}
         }
 
-        self.write_data_channel(stream, buf)?;
+        self.send_command("RNTO", to)?;
+        let res = self.read_message()?;
 
-        Ok(format!(
-            "Successfully transfered {} to {}",
-            file_path, server_path
-        ))
+        match &res[0..1] {
+            "2" => Ok(res),
+            _ => Err(FtpError::reply(res)),
+        }
     }
 
     /**
-     * This function is to transfer a file from the Ftp server to the local storage.
+     * This function is to transfer a given file to the Ftp server.
+     * file_path: The path of the file in the local storage.
+     * server_path: The path of the file that the file would be stored at after the execution.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn stor(&mut self, file_path: &str, server_path: &str) -> Result<String, FtpError> {
+        // Open the local file, it's read while the data is sent
+        let f = File::open(file_path)
+            .map_err(|e| FtpError::LocalIo(format!("can't open the local file {}: {}", file_path, e)))?;
+        self.stor_from(f, server_path)?;
+
+        Ok(format!(
+            "Successfully transfered {} to {}",
+            file_path, server_path
+        ))
+    }
+
+    /**
+     * This function is to transfer data read from a reader (e.g. stdin) to the Ftp server.
+     * The data is streamed as it's read, nothing is buffered in a file.
+     * data: Where the data is read from, until its end.
+     * server_path: The path of the file that the data would be stored at after the execution.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn stor_from(&mut self, data: impl Read, server_path: &str) -> Result<String, FtpError> {
+        // Request the data channel
+        let stream = self.pasv()?;
+        self.send_command("STOR", server_path)?;
+        
+        let res = self.read_message()?;
+        // Check if it's legit to send data
+        match &res[0..1] {
+            "1" => {},
+            _ => {return Err(FtpError::reply(res))}
+        }
+
+        self.write_data_channel(stream, data)
+    }
+
+    /**
+     * This function is to transfer a file from the Ftp server to the local storage.
+     * The file is received into a temporary file next to `file_path` (see part_path),
+     * which is flushed to disk and renamed to `file_path` once the server confirms the
+     * transfer, so `file_path` is either the complete file or left untouched.
      * file_path: The path of the file that the file would be stored at after the execution.
      * server_path: The path of the file in the server.
+     * resume: Keep the temporary file if the transfer fails, and continue from its end (REST)
+     * if it already exists. Only binary transfers are continued, as the offsets of an ASCII
+     * transfer don't match the local file.
      * Return Ok(String) with success message if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn retr(&mut self, file_path: &str, server_path: &str, resume: bool) -> Result<String, FtpError> {
+        let part = part_path(file_path);
+        // Continue from the end of the temporary file of a previous attempt
+        let offset = match resume && self.transfer_type == TransferType::Binary {
+            true => fs::metadata(&part).map(|m| m.len()).unwrap_or(0),
+            false => 0,
+        };
+
+        match self.retr_part(&part, server_path, offset) {
+            Ok(f) => commit_part(f, &part, file_path)?,
+            Err(e) => {
+                if !resume {
+                    let _ = fs::remove_file(&part);
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(format!("Successfully transfered {} to {}", server_path, file_path))
+    }
+
+    /**
+     * This function is to transfer a file from the Ftp server into a temporary file.
+     * This function can only be used inside the module.
+     * part: The path of the temporary file.
+     * server_path: The path of the file in the server.
+     * offset: The number of bytes already in the temporary file, the transfer starts there.
+     * Return Ok(File) with the temporary file if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    fn retr_part(&mut self, part: &str, server_path: &str, offset: u64) -> Result<File, FtpError> {
+        // Create local file
+        let mut f = match offset {
+            0 => File::create(part),
+            _ => File::options().append(true).open(part),
+        }
+        .map_err(|e| FtpError::LocalIo(format!("can't create {}: {}", part, e)))?;
+
+        self.retr_to(server_path, offset, &mut f)?;
+        Ok(f)
+    }
+
+    /**
+     * This function is to transfer a file from the Ftp server to a writer (e.g. stdout),
+     * as the data arrives. In ASCII mode, the line endings are converted to LF.
+     * server_path: The path of the file in the server.
+     * offset: The position in the file to start the transfer at (REST), 0 for the whole file.
+     * out: Where the data is written to, it's flushed at the end.
+     * Return Ok(u64) with the number of bytes received if no error occurs,
+     * otherwise, Err(FtpError) with error message.
      */
-    pub fn retr(&mut self, file_path: &str, server_path: &str) -> Result<String, String> {
+    pub fn retr_to(&mut self, server_path: &str, offset: u64, out: &mut impl Write) -> Result<u64, FtpError> {
         // Request data channel
         let stream = self.pasv()?;
-        self.send_message(format!("RETR {}\r\n", server_path))?;
+        if offset > 0 {
+            self.send_command("REST", &offset.to_string())?;
+            let res = self.read_message()?;
+            // Check if the server is able to restart at the offset
+            match &res[0..1] {
+                "3" => {},
+                _ => {return Err(FtpError::reply(res))}
+            }
+        }
+
+        self.send_command("RETR", server_path)?;
         let res = self.read_message()?;
         // Check if it's legit to receive data
         match &res[0..1] {
             "1" => {},
-            _ => {return Err(res)}
+            _ => {return Err(FtpError::reply(res))}
         }
 
-        let buf = self.read_data_channel(stream)?;
-        // Create local file
-        let mut f = File::create(file_path).map_err(|e| format!("{}", e))?;
-        // Write the data to the local file
-        f.write(&buf).map_err(|e| format!("{}", e))?;
+        // Write the data out, converting the line endings in ASCII mode
+        let received = match self.transfer_type {
+            TransferType::Ascii => {
+                let mut lf = ToLf::new(&mut *out);
+                let received = self.copy_data_channel(stream, &mut lf)?;
+                lf.finish().map_err(|e| FtpError::LocalIo(e.to_string()))?;
+                received
+            }
+            TransferType::Binary => self.copy_data_channel(stream, out)?,
+        };
+        out.flush().map_err(|e| FtpError::LocalIo(e.to_string()))?;
+        Ok(received)
+    }
 
-        Ok(format!("Successfully transfered {} to {}", server_path, file_path))
+    /**
+     * This function is to transfer a range of a file from the Ftp server. The transfer
+     * starts at the given offset (REST) and the data channel is closed as soon as `len`
+     * bytes are received, so the server may reply to the RETR with either a transfer
+     * complete or an aborted message. A NOOP is sent afterwards and every reply up to the
+     * one for the NOOP is consumed, which leaves the control channel ready for the next command.
+     * server_path: The path of the file in the server.
+     * offset: The position in the file to start the transfer at.
+     * len: The number of bytes to transfer.
+     * out: Where the received bytes are written to.
+     * Return Ok(String) with success message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn retr_range(
+        &mut self,
+        server_path: &str,
+        offset: u64,
+        len: u64,
+        out: &mut impl Write,
+    ) -> Result<String, FtpError> {
+        // Request data channel
+        let stream = self.pasv()?;
+        self.send_command("REST", &offset.to_string())?;
+        let res = self.read_message()?;
+        // Check if the server is able to restart at the offset
+        match &res[0..1] {
+            "3" => {},
+            _ => {return Err(FtpError::reply(res))}
+        }
+
+        self.send_command("RETR", server_path)?;
+        let res = self.read_message()?;
+        // Check if it's legit to receive data
+        match &res[0..1] {
+            "1" => {},
+            _ => {return Err(FtpError::reply(res))}
+        }
+
+        // Copy the range and close the data channel right after it
+        let watch = self.abort.watch(&stream);
+        let mut range = KeepAlive::new(&stream, self.tcp_control.get_ref(), self.keepalive, self.id);
+        let copied = copy_data(&mut (&mut range).take(len), out, false);
+        let (noops, lost) = (range.sent, range.lost.take());
+        drop(watch);
+        drop(stream);
+        self.log_data_closed(copied.as_ref().ok(), "received");
+        self.check_keepalive(lost)?;
+        if self.abort.is_aborted() {
+            return Err(self.abort_transfer(noops));
+        }
+
+        // Consume the reply to the RETR, whatever it is, along with the replies to the NOOPs
+        // sent during the transfer and the one sent now
+        self.send_command("NOOP", "")?;
+        let mut pending = noops + 1;
+        while pending > 0 {
+            if self.read_message()?.starts_with("200") {
+                pending -= 1;
+            }
+        }
+
+        match copied? {
+            n if n == len => Ok(format!(
+                "Successfully transfered bytes {} to {} of {}",
+                offset,
+                offset + len,
+                server_path
+            )),
+            n => Err(FtpError::Connection(format!(
+                "received {} of {} bytes at offset {} of {}",
+                n, len, offset, server_path
+            ))),
+        }
+    }
+
+    /**
+     * This function is to get the size of a file on the Ftp server.
+     * path: The path of the file.
+     * Return Ok(u64) with the size in bytes if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn size(&mut self, path: &str) -> Result<u64, FtpError> {
+        self.send_command("SIZE", path)?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => res[3..]
+                .trim()
+                .parse()
+                .map_err(|_| FtpError::Other(format!("invalid SIZE reply: {}", res))),
+            _ => Err(FtpError::reply(res)),
+        }
+    }
+
+    /**
+     * This function is to get the last modification time of a file on the Ftp server.
+     * The reply is "YYYYMMDDHHMMSS[.sss]" in UTC (RFC 3659 section 3).
+     * path: The path of the file.
+     * Return Ok(SystemTime) if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn mdtm(&mut self, path: &str) -> Result<SystemTime, FtpError> {
+        self.send_command("MDTM", path)?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => stat::parse_time(res[3..].trim())
+                .ok_or_else(|| FtpError::Other(format!("invalid MDTM reply: {}", res))),
+            _ => Err(FtpError::reply(res)),
+        }
+    }
+
+    /**
+     * This function is to get the last modification time of a file on the Ftp server with
+     * MDTM, or with the modify fact of MLST if the server only supports that.
+     * path: The path of the file.
+     * Return Ok(Some(SystemTime)) if no error occurs, Ok(None) if the server can't tell it,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn modified(&mut self, path: &str) -> Result<Option<SystemTime>, FtpError> {
+        if self.features.mdtm {
+            return self.mdtm(path).map(Some);
+        }
+        match self.features.mlst_facts.iter().any(|f| f == "modify") {
+            true => Ok(self.stat_path(path)?.modified),
+            false => Ok(None),
+        }
+    }
+
+    /**
+     * This function is to set the last modification time of a file on the Ftp server.
+     * MFMT (draft-somers-ftp-mfxx) is used if the server supports it. Otherwise
+     * "SITE UTIME" and MDTM with a time argument, which some servers accept instead, are tried.
+     * path: The path of the file.
+     * t: The modification time, only whole seconds are sent.
+     * Return Ok(true) if the time is set, Ok(false) if the server has no way to set it,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn set_modified(&mut self, path: &str, t: SystemTime) -> Result<bool, FtpError> {
+        let time = stat::format_time(t);
+        if self.features.mfmt {
+            self.send_command("MFMT", &format!("{} {}", time, path))?;
+            let res = self.read_message()?;
+
+            return match &res[0..1] {
+                "2" => Ok(true),
+                _ => Err(FtpError::reply(res)),
+            };
+        }
+
+        // Neither is advertised by FEAT, so a rejection only means it's not supported. Only
+        // the replies of a change count, as some servers answer unknown SITE commands with help
+        for (command, arg) in [("SITE", format!("UTIME {} {}", time, path)), ("MDTM", format!("{} {}", time, path))] {
+            self.send_command(command, &arg)?;
+            let res = self.read_message()?;
+            if matches!(&res[..3], "200" | "213" | "250" | "253") {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /**
+     * This function is to get the metadata of a single file or directory on the Ftp server.
+     * MLST is used if the server supports it. Otherwise the path is listed with LIST, which
+     * gives a single entry for a file, and SIZE and MDTM fill in what LIST can't tell.
+     * path: The path of the file or the directory.
+     * Return Ok(FileStat) if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn stat_path(&mut self, path: &str) -> Result<FileStat, FtpError> {
+        if self.features.has("MLST") {
+            self.send_command("MLST", path)?;
+            let res = self.read_message()?;
+
+            match &res[0..1] {
+                "2" => {},
+                _ => {return Err(FtpError::reply(res))}
+            }
+
+            // The entry is the line between the first and the last line of the reply
+            return res
+                .lines()
+                .nth(1)
+                .and_then(FileStat::parse_mlst)
+                .ok_or_else(|| FtpError::Other(format!("invalid MLST reply: {}", res)));
+        }
+
+        let listing = self.list_raw(path)?;
+        let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+        let entries: Vec<&str> = listing.lines().filter(|l| !l.is_empty() && !l.starts_with("total ")).collect();
+
+        // Anything but a single entry with the same name means the path is a directory
+        let mut stat = match entries.as_slice() {
+            [entry] => FileStat::parse_list(entry).filter(|stat| stat.name == name),
+            _ => None,
+        }
+        .unwrap_or_else(|| FileStat {
+            name: name.to_string(),
+            kind: Some("dir".to_string()),
+            ..FileStat::default()
+        });
+
+        if stat.kind.as_deref() == Some("file") {
+            if stat.size.is_none() && self.features.size {
+                stat.size = self.size(path).ok();
+            }
+            if self.features.mdtm {
+                stat.modified = self.mdtm(path).ok();
+            }
+        }
+        Ok(stat)
+    }
+
+    /**
+     * This function is to get the extensions supported by the Ftp server.
+     * Return Ok(Vec<String>) with one feature per entry (e.g. "REST STREAM") if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn feat(&mut self) -> Result<Vec<String>, FtpError> {
+        self.send_command("FEAT", "")?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            // The first and last lines are not features
+            "2" => Ok(res
+                .lines()
+                .skip(1)
+                .filter(|line| line.starts_with(' '))
+                .map(|line| line.trim().to_string())
+                .collect()),
+            _ => Err(FtpError::reply(res)),
+        }
+    }
+
+    /**
+     * This function is to ask the Ftp server for the checksum of a file.
+     * command: The checksum command, one of HASH, XSHA256, XMD5 and XCRC.
+     * path: The path of the file.
+     * Return Ok(String) with the reply text after the reply code if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn checksum(&mut self, command: &str, path: &str) -> Result<String, FtpError> {
+        self.send_command(command, path)?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => Ok(res[3..].trim().to_string()),
+            _ => Err(FtpError::reply(res)),
+        }
+    }
+
+    /**
+     * This function is to set an option of a command on the Ftp server.
+     * option: The command followed by its option, e.g. "HASH SHA-256".
+     * Return Ok(String) with the server message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn opts(&mut self, option: &str) -> Result<String, FtpError> {
+        self.send_command("OPTS", option)?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => Ok(res),
+            _ => Err(FtpError::reply(res)),
+        }
+    }
+
+    /**
+     * This function is to send any command to the Ftp server and read its reply, whatever
+     * the code is. The command can't contain CR, LF or NUL, so it's a single command.
+     * command: The command line without CRLF, e.g. "SITE IDLE 600" or "HELP".
+     * Return Ok(FtpReply) if the reply is read, otherwise, Err(FtpError) with error message.
+     */
+    pub fn raw_command(&mut self, command: &str) -> Result<FtpReply, FtpError> {
+        let (verb, arg) = command.split_once(' ').unwrap_or((command, ""));
+        self.send_command(verb, arg)?;
+        self.read_reply()
+    }
+
+    /**
+     * This function is to send any command that sends its result over a data channel, e.g.
+     * "MLSD dir" or "STAT -la", and read the result to `out` unchanged.
+     * command: The command line without CRLF.
+     * out: Where the data is written to.
+     * Return Ok(FtpReply) with the reply after the data, or the first reply if the server
+     * doesn't open the data channel, otherwise, Err(FtpError) with error message.
+     */
+    pub fn raw_command_data(&mut self, command: &str, out: &mut impl Write) -> Result<FtpReply, FtpError> {
+        let mut stream = self.pasv()?;
+        let reply = self.raw_command(command)?;
+        if !reply.text.starts_with('1') {
+            return Ok(reply);
+        }
+
+        let watch = self.abort.watch(&stream);
+        let copied = copy_data(&mut stream, out, false);
+        drop(watch);
+        drop(stream);
+        self.log_data_closed(copied.as_ref().ok(), "received");
+        if self.abort.is_aborted() {
+            return Err(self.abort_transfer(0));
+        }
+        // The reply is read even if the copy failed so the control channel stays in sync
+        let reply = self.read_reply()?;
+        copied?;
+        Ok(reply)
+    }
+
+    /**
+     * This function is to read a reply of the Ftp server with its code.
+     * This function can only be used inside the module.
+     * Return Ok(FtpReply) if no error occurs, otherwise, Err(FtpError) with error message.
+     */
+    fn read_reply(&mut self) -> Result<FtpReply, FtpError> {
+        let text = self.read_message()?;
+        let code = text
+            .get(0..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| FtpError::Other(format!("invalid reply: {}", text)))?;
+        Ok(FtpReply { code, text })
+    }
+
+    /**
+     * This function is to send a SITE command, which are the commands specific to the server.
+     * command: The SITE command followed by its arguments, e.g. "CHMOD 755 script.sh".
+     * Return Ok(String) with the server message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn site(&mut self, command: &str) -> Result<String, FtpError> {
+        self.send_command("SITE", command)?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => Ok(res),
+            _ => Err(FtpError::reply(res)),
+        }
     }
 
     /**
-     * This function is to request a data channel.
+     * This function is to change the permissions of a file or directory on the Ftp server
+     * with SITE CHMOD, which most Unix servers support.
+     * path: The path of the file or the directory.
+     * mode: The permission bits, e.g. 0o755.
+     * Return Ok(String) with the server message if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    pub fn chmod(&mut self, path: &str, mode: u32) -> Result<String, FtpError> {
+        self.site(&format!("CHMOD {:o} {}", mode & 0o7777, path))
+    }
+
+    /**
+     * This function is to request a data channel. EPSV is used if the server supports it,
+     * or if the control channel is IPv6, which PASV can't describe.
      * This function can only be used inside the module.
      * Return Ok(TcpStream) if no error occurs,
-     * otherwise, Err(String) with error message.
+     * otherwise, Err(FtpError) with error message.
      */
     #[allow(unused)]
-    fn pasv(&mut self) -> Result<TcpStream, String> {
-        match self.send_message("PASV F\r\n".to_string()) {
+    fn pasv(&mut self) -> Result<TcpStream, FtpError> {
+        if self.abort.is_aborted() {
+            return Err(FtpError::Aborted("the transfer was aborted".to_string()));
+        }
+
+        let ipv6 = self.tcp_control.get_ref().peer_addr().map(|a| a.is_ipv6()).unwrap_or(false);
+        if self.features.epsv || ipv6 {
+            return self.epsv();
+        }
+
+        match self.send_command("PASV", "F") {
             Ok(_) => {}
             Err(e) => {
                 return Err(e);
@@ -397,7 +1458,7 @@ impl FtpStream {
 
         match &ip[0..1] {
             "2" => {},
-            _ => {return Err(ip)}
+            _ => {return Err(FtpError::reply(ip))}
         }
 
         // Extract the ip address
@@ -416,10 +1477,342 @@ impl FtpStream {
                 // Transfer the port number into decimal format
                 let port = (ip5 << 8) + ip6;
 
-                return TcpStream::connect(format!("{}.{}.{}.{}:{}", ip1, ip2, ip3, ip4, port))
-                    .map_err(|e| format!("can't connect to file stream at {},  error: {}", ip, e));
+                open((Ipv4Addr::new(ip1, ip2, ip3, ip4), port), self.timeout)
+                    .map(|stream| self.log_data_opened(stream))
+                    .map_err(|e| FtpError::channel(&format!("can't connect to file stream at {}", ip), e))
+            }
+            None => Err(FtpError::Other(format!("Didn't capture the IP address {}", ip))),
+        }
+    }
+
+    /**
+     * This function is to request a data channel with EPSV (RFC 2428). The server only
+     * sends the port, the data channel is at the same address as the control channel.
+     * This function can only be used inside the module.
+     * Return Ok(TcpStream) if no error occurs,
+     * otherwise, Err(FtpError) with error message.
+     */
+    fn epsv(&mut self) -> Result<TcpStream, FtpError> {
+        self.send_command("EPSV", "")?;
+        let res = self.read_message()?;
+
+        match &res[0..1] {
+            "2" => {},
+            _ => {return Err(FtpError::reply(res))}
+        }
+
+        // Extract the port number
+        let port: u16 = EPSV_REGEX
+            .captures(&res)
+            .and_then(|cap| cap.get(1).unwrap().as_str().parse().ok())
+            .ok_or_else(|| FtpError::Other(format!("Didn't capture the port number {}", res)))?;
+        let addr = self
+            .tcp_control
+            .get_ref()
+            .peer_addr()
+            .map_err(|e| FtpError::Connection(e.to_string()))?;
+
+        open((addr.ip(), port), self.timeout)
+            .map(|stream| self.log_data_opened(stream))
+            .map_err(|e| FtpError::channel(&format!("can't connect to file stream at {}", res), e))
+    }
+
+    /**
+     * This function is to log that a data channel is opened, with the address it's connected to.
+     * This function can only be used inside the module.
+     * Return the TcpStream of the data channel.
+     */
+    fn log_data_opened(&self, stream: TcpStream) -> TcpStream {
+        match stream.peer_addr() {
+            Ok(addr) => logger::log(Level::Info, self.id, format_args!("data channel opened to {}", addr)),
+            Err(_) => logger::log(Level::Info, self.id, format_args!("data channel opened")),
+        }
+        stream
+    }
+
+    /**
+     * This function is to log that a data channel is closed, with the number of bytes
+     * received or sent over it, None if the copy failed.
+     * This function can only be used inside the module.
+     */
+    fn log_data_closed(&self, bytes: Option<&u64>, direction: &str) {
+        match bytes {
+            Some(n) => logger::log(Level::Info, self.id, format_args!("data channel closed, {} bytes {}", n, direction)),
+            None => logger::log(Level::Info, self.id, format_args!("data channel closed, the transfer failed")),
+        }
+    }
+}
+
+/**
+ * This function is to check that a command line can't be ended early by its argument
+ * (see send_command).
+ * Return Ok(()) if the command and the argument have no CR, LF or NUL,
+ * otherwise, Err(FtpError) with error message.
+ */
+fn check_command(command: &str, arg: &str) -> Result<(), FtpError> {
+    match command.chars().chain(arg.chars()).find(|c| matches!(c, '\r' | '\n' | '\0')) {
+        Some(c) => Err(FtpError::Other(format!(
+            "the argument of {} contains the control character {:?}, which is not allowed",
+            command, c
+        ))),
+        None => Ok(()),
+    }
+}
+
+/**
+ * This function is to encode a command line as it's written to the control channel
+ * (see send_message): in the given encoding, with every 0xFF byte (Telnet IAC) doubled.
+ * Return Ok(Vec<u8>) with the bytes of the line, otherwise, Err(FtpError) if a character
+ * can't be encoded.
+ */
+fn encode_line(encoding: &'static Encoding, msg: &str) -> Result<Vec<u8>, FtpError> {
+    let (bytes, _, unmappable) = encoding.encode(msg);
+    // The encoder would replace the character with an HTML entity, which changes the path
+    if unmappable {
+        return Err(FtpError::Other(format!(
+            "can't send the argument of {} in {} encoding",
+            msg.split(' ').next().unwrap_or("").trim_end(),
+            encoding.name()
+        )));
+    }
+
+    // Escape the Telnet IAC
+    let mut line = Vec::with_capacity(bytes.len());
+    for &b in bytes.iter() {
+        line.push(b);
+        if b == 0xFF {
+            line.push(0xFF);
+        }
+    }
+    Ok(line)
+}
+
+/**
+ * This function is to extract the path of a 257 reply (RFC 959 appendix II), which is
+ * enclosed in double quotes, a double quote in the path being doubled,
+ * e.g. '257 "/say ""hi""" is the current directory' -> '/say "hi"'.
+ * Return Some(String) with the path, or None if there is no quoted path in the reply.
+ */
+fn parse_quoted_path(res: &str) -> Option<String> {
+    let mut chars = res[res.find('"')? + 1..].chars().peekable();
+    let mut path = String::new();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('"', Some('"')) => {
+                chars.next();
+                path.push('"');
             }
-            None => return Err(format!("Didn't capture the IP address {}", ip)),
+            ('"', _) => return Some(path),
+            (c, _) => path.push(c),
+        }
+    }
+
+    None
+}
+
+/**
+ * This function is to open a TCP connection, giving up after the timeout if there is one.
+ * The timeout is also used for every read and write on the connection.
+ * addr: The address to connect to, every address it resolves to is tried in turn.
+ * timeout: How long to wait, None waits forever.
+ * TCP keepalive is turned on for the connection.
+ * Return Ok(TcpStream) if no error occurs, otherwise Err(io::Error) of the last address tried.
+ */
+fn open(addr: impl ToSocketAddrs, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let stream = match timeout {
+        None => TcpStream::connect(addr)?,
+        Some(t) => {
+            let mut last = io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to");
+            let mut stream = None;
+            for a in addr.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&a, t) {
+                    Ok(s) => {
+                        stream = Some(s);
+                        break;
+                    }
+                    Err(e) => last = e,
+                }
+            }
+            stream.ok_or(last)?
+        }
+    };
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    // TCP keepalive probes an idle connection, so a connection that's gone is noticed and
+    // NAT tables and firewalls keep the entry of one that's alive
+    SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(TCP_KEEPALIVE))?;
+    Ok(stream)
+}
+
+/**
+ * This function is to check the reply the server sends after a data transfer.
+ * res: The reply to check.
+ * Return Ok(()) if the server confirms the transfer with 2xx,
+ * otherwise, Err(FtpError::TransferFailed) carrying the reply.
+ */
+fn check_transfer_reply(res: String) -> Result<(), FtpError> {
+    match &res[0..1] {
+        "2" => Ok(()),
+        _ => Err(FtpError::TransferFailed {
+            code: res.get(0..3).and_then(|code| code.parse().ok()).unwrap_or(0),
+            reply: res,
+        }),
+    }
+}
+
+/**
+ * This function is to copy all the data of a transfer, like io::copy, but a failed read is
+ * told apart from a failed write, as only one side is the data channel and the other one is
+ * a local file or stream.
+ * from: Where the data is read from.
+ * to: Where the data is written to.
+ * upload: Whether `to` is the data channel, otherwise `from` is.
+ * Return Ok(u64) with the number of bytes copied if no error occurs, otherwise,
+ * Err(FtpError), which is FtpError::LocalIo if the local side failed.
+ */
+fn copy_data(from: &mut impl Read, to: &mut impl Write, upload: bool) -> Result<u64, FtpError> {
+    let failed = |channel: bool, e: io::Error| match channel {
+        true => FtpError::channel("the data channel failed", e),
+        false => FtpError::LocalIo(e.to_string()),
+    };
+
+    let mut buf = [0; 8192];
+    let mut copied = 0;
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) => return Ok(copied),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(failed(!upload, e)),
+        };
+        to.write_all(&buf[..n]).map_err(|e| failed(upload, e))?;
+        copied += n as u64;
+    }
+}
+
+/**
+ * This function is to get the path of the temporary file a download is received into.
+ * It's a hidden file in the same directory as `file_path`, so it can be renamed to
+ * `file_path` without copying, e.g. "dir/file.txt" -> "dir/.file.txt.part".
+ */
+pub fn part_path(file_path: &str) -> String {
+    let path = Path::new(file_path);
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.part", name)).to_string_lossy().to_string()
+}
+
+/**
+ * This function is to move a completely downloaded temporary file into place.
+ * The data is flushed to disk before the rename, so `file_path` never refers to a
+ * file with missing data, even after a crash.
+ * f: The temporary file.
+ * part: The path of the temporary file.
+ * file_path: The final path of the file.
+ * Return Ok(()) if no error occurs, otherwise, Err(FtpError) with error message.
+ */
+pub fn commit_part(f: File, part: &str, file_path: &str) -> Result<(), FtpError> {
+    f.sync_all().map_err(|e| FtpError::LocalIo(format!("can't write {}: {}", part, e)))?;
+    drop(f);
+    fs::rename(part, file_path)
+        .map_err(|e| FtpError::LocalIo(format!("can't rename {} to {}: {}", part, file_path, e)))?;
+
+    // Flush the rename to disk as well, this is best effort as not every platform allows it
+    let dir = match Path::new(file_path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::WINDOWS_1252;
+    use std::{net::TcpListener, thread, time::Instant};
+
+    #[test]
+    fn command_with_control_characters_is_rejected() {
+        assert!(check_command("RETR", "file.txt").is_ok());
+        assert!(check_command("PASS", "p@ss word").is_ok());
+        for arg in ["a\r\nDELE b", "a\nDELE b", "a\rb", "a\0b"] {
+            assert!(matches!(check_command("RETR", arg), Err(FtpError::Other(_))), "{:?}", arg);
         }
+        assert!(check_command("NOOP\r\nDELE a", "").is_err());
+    }
+
+    #[test]
+    fn iac_is_doubled() {
+        assert_eq!(encode_line(UTF_8, "CWD dir\r\n").unwrap(), b"CWD dir\r\n");
+        assert_eq!(encode_line(WINDOWS_1252, "CWD \u{ff}\r\n").unwrap(), b"CWD \xff\xff\r\n");
+        assert_eq!(encode_line(WINDOWS_1252, "CWD \u{ff}\u{ff}a\r\n").unwrap(), b"CWD \xff\xff\xff\xffa\r\n");
+        // UTF-8 never has a 0xFF byte
+        assert_eq!(encode_line(UTF_8, "CWD \u{ff}\r\n").unwrap(), "CWD \u{ff}\r\n".as_bytes());
+        assert!(encode_line(WINDOWS_1252, "CWD \u{65e5}\r\n").is_err());
+    }
+
+    #[test]
+    fn command_line_on_the_wire() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"220 ready\r\n").unwrap();
+            let mut received = vec![];
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let mut ftp = FtpStream::new("127.0.0.1", port, Some(Duration::from_secs(5))).unwrap();
+        ftp.set_encoding(WINDOWS_1252);
+        ftp.send_command("CWD", "caf\u{e9} \u{ff}").unwrap();
+        assert!(ftp.send_command("RETR", "a\r\nDELE b").is_err());
+        ftp.send_command("NOOP", "").unwrap();
+        drop(ftp);
+
+        assert_eq!(server.join().unwrap(), b"CWD caf\xe9 \xff\xff\r\nNOOP\r\n");
+    }
+
+    #[test]
+    fn abort_stops_at_the_reply_to_the_last_noop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"220 ready\r\n200 NOOP ok\r\n426 aborted\r\n226 ABOR ok\r\n500 NOOP unknown\r\n").unwrap();
+            let mut received = vec![];
+            stream.read_to_end(&mut received).unwrap();
+        });
+
+        let mut ftp = FtpStream::new("127.0.0.1", port, Some(Duration::from_secs(30))).unwrap();
+        let start = Instant::now();
+        let err = ftp.abort_transfer(1).to_string();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(err.contains("426 aborted") && err.contains("226 ABOR ok"), "{}", err);
+        assert!(!err.contains("lost") && !err.contains("500"), "{}", err);
+        drop(ftp);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn quoted_path() {
+        assert_eq!(parse_quoted_path("257 \"/home/user\" is the current directory").as_deref(), Some("/home/user"));
+        assert_eq!(parse_quoted_path("257 \"/dir with spaces\"").as_deref(), Some("/dir with spaces"));
+        assert_eq!(parse_quoted_path("257 \"\" created").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn quoted_path_with_doubled_quotes() {
+        assert_eq!(parse_quoted_path("257 \"/say \"\"hi\"\"\" is the current directory").as_deref(), Some("/say \"hi\""));
+        assert_eq!(parse_quoted_path("257 \"\"\"\"\"\" created").as_deref(), Some("\"\""));
+    }
+
+    #[test]
+    fn quoted_path_without_quotes() {
+        assert_eq!(parse_quoted_path("257 /home/user is the current directory"), None);
+        assert_eq!(parse_quoted_path("257 \"/home/user is the current directory"), None);
     }
 }
@@ -0,0 +1,117 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use crate::logger::{self, Level};
+
+/**
+ * This struct wraps the data channel of a transfer and sends NOOP over the control channel
+ * whenever the interval has passed since the last one, so a firewall doesn't drop the control
+ * channel while it's idle during a long transfer. The replies are left in the control
+ * channel, `sent` tells how many to read once the transfer is over. If a NOOP can't be
+ * written, the transfer stops and `lost` holds the error of the control channel.
+ */
+pub struct KeepAlive<'a, S> {
+    inner: S,
+    control: &'a TcpStream,
+    // None sends no NOOP at all
+    interval: Option<Duration>,
+    last: Instant,
+    // The connection id in the log
+    id: u64,
+    pub sent: usize,
+    pub lost: Option<io::Error>,
+}
+
+impl<'a, S> KeepAlive<'a, S> {
+    pub fn new(inner: S, control: &'a TcpStream, interval: Option<Duration>, id: u64) -> Self {
+        KeepAlive {
+            inner,
+            control,
+            interval,
+            last: Instant::now(),
+            id,
+            sent: 0,
+            lost: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /**
+     * This function is to send NOOP if the interval has passed since the last one.
+     */
+    fn tick(&mut self) -> io::Result<()> {
+        match self.interval {
+            Some(interval) if self.last.elapsed() >= interval => {
+                logger::log(Level::Debug, self.id, format_args!("> NOOP (keepalive)"));
+                let mut control = self.control;
+                if let Err(e) = control.write_all(b"NOOP\r\n") {
+                    let stop = io::Error::new(e.kind(), "the control channel failed");
+                    self.lost = Some(e);
+                    return Err(stop);
+                }
+                self.sent += 1;
+                self.last = Instant::now();
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<S: Read> Read for KeepAlive<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.tick()?;
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for KeepAlive<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tick()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Shutdown, TcpListener};
+
+    #[test]
+    fn failed_noop_is_kept() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let control = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let _server = listener.accept().unwrap();
+        control.shutdown(Shutdown::Write).unwrap();
+
+        let mut stream = KeepAlive::new(&b"data"[..], &control, Some(Duration::ZERO), 0);
+        assert!(stream.read(&mut [0; 4]).is_err());
+        assert_eq!(stream.sent, 0);
+        assert!(stream.lost.is_some());
+    }
+
+    #[test]
+    fn noop_is_sent_after_the_interval() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let control = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let mut stream = KeepAlive::new(&b"data"[..], &control, Some(Duration::ZERO), 0);
+        assert_eq!(stream.read(&mut [0; 4]).unwrap(), 4);
+        assert_eq!(stream.sent, 1);
+        assert!(stream.lost.is_none());
+        let mut noop = [0; 6];
+        server.read_exact(&mut noop).unwrap();
+        assert_eq!(&noop, b"NOOP\r\n");
+    }
+}
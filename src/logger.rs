@@ -0,0 +1,153 @@
+use clap::ValueEnum;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::stat;
+
+/**
+ * This enum is the level of a log message (--log-level). A message is written when its
+ * level is at most the level of the logger:
+ * error: The control channel failed, e.g. a timeout.
+ * warn: The replies of the server that are failures (4xx and 5xx).
+ * info: The connections, and the data channels opened and closed with their bytes.
+ * debug: Every command sent and every reply received.
+ */
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Off => "OFF",
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        };
+        write!(f, "{:5}", name)
+    }
+}
+
+/**
+ * This struct is where the log messages go, stderr or the transcript file, and up to
+ * which level.
+ */
+struct Logger {
+    level: Level,
+    out: Box<dyn Write + Send>,
+}
+
+// The logger is shared by every connection, as the connections of a transfer run on several threads
+lazy_static! {
+    static ref LOGGER: Mutex<Logger> = Mutex::new(Logger {
+        level: Level::Off,
+        out: Box::new(io::stderr()),
+    });
+}
+
+// The id of the next connection, starting at 1
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/**
+ * This function is to set up the logger, before any connection is made.
+ * level: The highest level that is written.
+ * path: The transcript file the messages are appended to, or None for stderr, as stdout
+ * may carry a file.
+ * Return Ok(()) if no error occurs, otherwise, Err(String) if the file can't be opened.
+ */
+pub fn init(level: Level, path: Option<&str>) -> Result<(), String> {
+    let out: Box<dyn Write + Send> = match path {
+        Some(path) => Box::new(
+            File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("can't open the log file {}: {}", path, e))?,
+        ),
+        None => Box::new(io::stderr()),
+    };
+    *LOGGER.lock().unwrap() = Logger { level, out };
+    Ok(())
+}
+
+/**
+ * This function is to get a new connection id, which tells apart the messages of the
+ * connections that run at the same time.
+ */
+pub fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/**
+ * This function is to write a message of a connection as a single line, e.g.
+ * "2024-01-01T12:00:00.123Z DEBUG #1 > USER anonymous".
+ * level: The level of the message, it's dropped if the logger is set to a lower level.
+ * id: The id of the connection.
+ * message: The message, usually built with format_args!.
+ */
+pub fn log(level: Level, id: u64, message: fmt::Arguments) {
+    let mut logger = LOGGER.lock().unwrap();
+    if level == Level::Off || level > logger.level {
+        return;
+    }
+
+    let now = SystemTime::now();
+    let millis = now.duration_since(UNIX_EPOCH).map(|d| d.subsec_millis()).unwrap_or(0);
+    let time = stat::format_iso(now);
+    // A failed write to the log must not fail the transfer
+    let _ = writeln!(
+        logger.out,
+        "{}.{:03}Z {} #{} {}",
+        time.trim_end_matches('Z'),
+        millis,
+        level,
+        id,
+        message
+    );
+}
+
+/**
+ * This function is to get the argument of a command as it's written to the log, the
+ * password of PASS and the account of ACCT are masked.
+ */
+pub fn masked<'a>(command: &str, arg: &'a str) -> &'a str {
+    match command.to_ascii_uppercase().as_str() {
+        "PASS" | "ACCT" if !arg.is_empty() => "****",
+        _ => arg,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_is_masked() {
+        assert_eq!(masked("PASS", "hunter2"), "****");
+        assert_eq!(masked("pass", "hunter2"), "****");
+        assert_eq!(masked("ACCT", "billing"), "****");
+        assert_eq!(masked("PASS", ""), "");
+    }
+
+    #[test]
+    fn other_commands_are_unchanged() {
+        assert_eq!(masked("USER", "alice"), "alice");
+        assert_eq!(masked("RETR", "PASS"), "PASS");
+        assert_eq!(masked("STOR", "pass.txt"), "pass.txt");
+        assert_eq!(masked("NOOP", ""), "");
+    }
+}
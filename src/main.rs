@@ -1,30 +1,167 @@
 use clap::{Parser, ValueEnum};
-use regex::Regex;
-use std::fs;
+use encoding_rs::{Encoding, UTF_8};
+use serde_json::json;
+use std::{
+    fmt, fs, io,
+    path::Path,
+    process::{self, ExitCode},
+    time::{Duration, Instant},
+};
 
-use ftp::FtpStream;
+use abort::AbortHandle;
+use config::{Config, Profile};
+use credentials::Credentials;
+use exit::{Failure, Kind};
+use ftp::{FtpError, FtpStream, TransferType};
+use logger::Level;
+use output::Format;
+use url::FtpUrl;
+use verify::Checksum;
+mod abort;
+mod ascii;
+mod config;
+mod credentials;
+mod exit;
+mod features;
+mod output;
 mod ftp;
+mod keepalive;
+mod logger;
+mod scheduler;
+mod stat;
+mod url;
+mod verify;
 
 #[macro_use]
 extern crate lazy_static;
 
+// The local path that means stdout for a download and stdin for an upload
+const STDIO: &str = "-";
+
+// Aborts the transfers of every connection on Ctrl-C
+lazy_static! {
+    static ref ABORT: AbortHandle = AbortHandle::default();
+}
+
 /**
  * This struct is used to extract the command line arguments.
  */
 #[derive(Parser, Debug)]
-#[command(author, about, long_about = None)]
+#[command(author, about, long_about = None, after_help = exit::EXIT_CODES)]
 struct Cli {
     /// The operation to execute.
     #[arg(value_enum)]
     operation: Operation,
-    /// Parameters for the given operation. This parameter is mandatory.
+    /// Parameters for the given operation. This parameter is mandatory. For 'cp', '-' as the
+    /// local path means stdin. For 'cp' and 'mv', a local directory or a URL ending with '/' as the
+    /// source means every file in it and in its subdirectories, which are created as needed. Entries
+    /// that are neither files nor directories are reported as skipped and make the exit code non-zero.
+    /// '@name/path' is a profile of --config, write a local path starting with '@' as './@file'
     param1: String,
-    /// Parameters for the given operation. This parameter is only mandatory when using 'cp' or 'mv'
+    /// Parameters for the given operation. This parameter is only mandatory when using 'cp', 'mv', 'chmod' or 'quote'.
+    /// For 'cp', '-' as the local path means stdout. Like PARAM1, write a local path starting with '@' as './@file'
     param2: Option<String>,
 
-    /// Print all messages to and from the FTP server
+    /// Print all messages to and from the FTP server, the same as --log-level debug
     #[arg(short, long)]
-    verbose:  bool
+    verbose:  bool,
+
+    /// Level of the protocol log written to stderr or --log-file. The default is off, or debug
+    /// with --verbose or --log-file
+    #[arg(long, value_enum)]
+    log_level: Option<Level>,
+
+    /// Append the protocol log to this file instead of stderr. Passwords are masked
+    #[arg(long)]
+    log_file: Option<String>,
+
+    /// Number of connections used to transfer the files of a directory at the same time
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    parallel: u16,
+
+    /// Number of connections used to download a single large file in ranges at the same time
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    segments: u16,
+
+    /// Verify the size (and checksum, when the server supports it) of every transferred file.
+    /// 'mv' keeps the source if the verification fails
+    #[arg(long)]
+    verify: bool,
+
+    /// Upload to a temporary name and rename it to the final name once the upload is complete
+    #[arg(long)]
+    atomic: bool,
+
+    /// Prefix of the temporary name used by --atomic
+    #[arg(long, default_value = "")]
+    temp_prefix: String,
+
+    /// Suffix of the temporary name used by --atomic
+    #[arg(long, default_value = ".part")]
+    temp_suffix: String,
+
+    /// Keep the partial file of a failed download and continue from its end on the next run.
+    /// Downloads in segments always start over
+    #[arg(long)]
+    resume: bool,
+
+    /// Transfer files in ASCII mode (TYPE A), converting the line endings between CRLF and LF
+    #[arg(long, conflicts_with_all = ["binary", "auto_type"])]
+    ascii: bool,
+
+    /// Transfer files in binary mode (TYPE I), unchanged. This is the default
+    #[arg(long, conflicts_with = "auto_type")]
+    binary: bool,
+
+    /// Transfer text files (by extension, e.g. .txt or .csv) in ASCII mode and the others in binary mode
+    #[arg(long)]
+    auto_type: bool,
+
+    /// Encoding of the paths, server messages and listings, e.g. utf-8, latin1, shift_jis or windows-1251.
+    /// The default is utf-8
+    #[arg(long, value_parser = parse_encoding)]
+    encoding: Option<&'static Encoding>,
+
+    /// Seconds to wait for connecting and for every read and write before giving up.
+    /// The default is to wait forever
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Seconds between the NOOPs sent over the control channel during a transfer, so a firewall
+    /// doesn't drop it while it's idle. The default is to send none
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    keepalive: Option<u64>,
+
+    /// Configuration file with the profiles used as @name/path, the default is ~/.config/4700ftp/config.toml
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Keep the modification time of the source, if the server can tell (download) or set (upload) it
+    #[arg(long)]
+    preserve: bool,
+
+    /// Give uploaded files the permissions of the local file with SITE CHMOD (Unix only)
+    #[arg(long)]
+    preserve_mode: bool,
+
+    /// Open a data channel for 'quote', for commands that send their result over it, e.g. MLSD
+    #[arg(long)]
+    data: bool,
+
+    /// Print the results as text, or as JSON with one object per line for scripts
+    #[arg(long, value_enum, default_value = "text")]
+    output: Format,
+
+    /// Read the password from the first line of stdin instead of the URL, FTP_PASSWORD or ~/.netrc
+    #[arg(long)]
+    password_stdin: bool
+}
+
+/**
+ * This function is to look up an encoding by its label for the --encoding argument.
+ */
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding {}", label))
 }
 
 /**
@@ -36,170 +173,842 @@ enum Operation {
     Mkdir,
     Rm,
     Rmdir,
+    Stat,
+    Chmod,
+    Quote,
     Cp,
     Mv
 }
 
-// Allocate static memory for REGEX_USER and REGEX_ANONYMOUS
-lazy_static! {
-    static ref REGEX_USER: Regex = Regex::new(r"ftp://([^:]+)(:.+)?@([^:/]+)(:\d+)?/(.*)").unwrap();
-    static ref REGEX_ANONYMOUS: Regex = Regex::new(r"ftp://([^:@/]+)(:\d+)?/([^@]*)").unwrap();
+/**
+ * This struct describes a single file to be transferred by 'cp' or 'mv'.
+ */
+#[derive(Clone, Debug)]
+struct TransferJob {
+    local_path: String,
+    server_path: String,
+    upload: bool,
+    transfer_type: TransferType,
+}
+
+impl TransferJob {
+    /**
+     * This function is to get the source and the destination of the file.
+     */
+    fn endpoints(&self) -> (&str, &str) {
+        match self.upload {
+            true => (&self.local_path, &self.server_path),
+            false => (&self.server_path, &self.local_path),
+        }
+    }
+}
+
+impl fmt::Display for TransferJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (source, destination) = self.endpoints();
+        write!(f, "{} -> {}", source, destination)
+    }
 }
 
 /**
- * This function is to extract username, password, host, port, and path from
- * the given URL.
+ * This struct is the outcome of a successful 'cp' or 'mv' of a single file: the steps
+ * completed, the size of the file, how long it took and the checksum it was verified with.
  */
-fn extract_param(param: &str) -> Result<(&str, &str, &str, &str, &str), String> {
+struct Transferred {
+    job: TransferJob,
+    steps: Vec<&'static str>,
+    /// The size of the local file, None if it's stdin
+    bytes: Option<u64>,
+    duration: Duration,
+    checksum: Option<Checksum>,
+}
 
-    if REGEX_USER.is_match(param) {
-        let cap = REGEX_USER.captures(param).unwrap();
+impl Transferred {
+    /**
+     * This function is to describe the transfer as a JSON object.
+     */
+    fn to_json(&self) -> serde_json::Value {
+        let (source, destination) = self.job.endpoints();
+        json!({
+            "type": "transfer",
+            "ok": true,
+            "source": source,
+            "destination": destination,
+            "bytes": self.bytes,
+            "duration": self.duration.as_secs_f64(),
+            "checksum": self.checksum.as_ref().map(|c| json!({"algorithm": c.algorithm, "digest": c.digest})),
+            "steps": self.steps,
+        })
+    }
+}
 
-        let username = cap.get(1).unwrap().as_str();
-        let password = match cap.get(2) {
-            Some(p) => &p.as_str()[1..],
-            None => ""
-        };
-        let host = cap.get(3).unwrap().as_str();
-        let port = match cap.get(4) {
-            Some(p) => &p.as_str()[1..],
-            None => ""
-        };
-        let path = cap.get(5).unwrap().as_str();
-
-        Ok((username, password, host, port, path))
-    } else if REGEX_ANONYMOUS.is_match(param) {
-        let cap = REGEX_ANONYMOUS.captures(param).unwrap();
-        let username = "anonymous";
-        let password = "";
-        let host = cap.get(1).unwrap().as_str();
-        let port =  match cap.get(2) {
-            Some(p) => &p.as_str()[1..],
-            None => ""
-        };
-        let path = cap.get(3).unwrap().as_str();
+impl fmt::Display for Transferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.job, self.steps.join(", "))
+    }
+}
 
-        Ok((username, password, host, port, path))
+/**
+ * This function is to choose the transfer type of a file. --ascii and --binary come first,
+ * then the typecode of the URL, then the extension of the file if --auto-type is given.
+ * cli: The command line arguments.
+ * typecode: The typecode of the URL, if any.
+ * path: The path of the file.
+ */
+fn transfer_type(cli: &Cli, typecode: Option<TransferType>, path: &str) -> TransferType {
+    if cli.ascii {
+        TransferType::Ascii
+    } else if cli.binary {
+        TransferType::Binary
+    } else if let Some(t) = typecode {
+        t
+    } else if cli.auto_type && ascii::is_text_file(path) {
+        TransferType::Ascii
     } else {
-        Err("Given URL is invalid".to_string())
+        TransferType::Binary
     }
 }
 
 /**
- * This is the main driver of this CLI.
+ * This function is to get the name of an operation as it's written on the command line.
  */
-fn main() -> Result<(), String> {
+fn operation_name(operation: &Operation) -> &'static str {
+    match operation {
+        Operation::Ls => "ls",
+        Operation::Mkdir => "mkdir",
+        Operation::Rm => "rm",
+        Operation::Rmdir => "rmdir",
+        Operation::Stat => "stat",
+        Operation::Chmod => "chmod",
+        Operation::Quote => "quote",
+        Operation::Cp => "cp",
+        Operation::Mv => "mv",
+    }
+}
+
+/**
+ * This function is to tell whether the given parameter refers to the server, i.e. it's an
+ * ftp URL or a profile of the configuration file, rather than a local path.
+ */
+fn is_remote(param: &str) -> bool {
+    FtpUrl::is_url(param) || config::is_profile(param)
+}
+
+/**
+ * This function is to parse a parameter that refers to the server, an ftp URL or
+ * "@name/path" with a profile of the configuration file.
+ * Return Ok((FtpUrl, Profile)) if no error occurs, the profile is empty for an URL,
+ * otherwise Err(String)
+ */
+fn remote(config: &Config, param: &str) -> Result<(FtpUrl, Profile), String> {
+    match config::is_profile(param) {
+        true => config.resolve(param).map(|(url, profile)| (url, profile.clone())),
+        false => Ok((FtpUrl::parse(param)?, Profile::default())),
+    }
+}
+
+/**
+ * This function is to create the control channel to the Ftp server of the URL, login with
+ * the given credentials, and configure the session. The encoding, the timeout and the
+ * keepalive of the command line take precedence over the ones of the profile.
+ * Return Ok(FtpStream) if no error occurs, otherwise Err(FtpError)
+ */
+fn connect(cli: &Cli, url: &FtpUrl, profile: &Profile, creds: &Credentials) -> Result<FtpStream, FtpError> {
+    let timeout = cli.timeout.or(profile.timeout).map(Duration::from_secs);
+    let mut ftp = FtpStream::new(&url.host, url.port.unwrap_or(21), timeout)?;
+    ftp.set_encoding(cli.encoding.or(profile.encoding).unwrap_or(UTF_8));
+    ftp.set_keepalive(cli.keepalive.or(profile.keepalive).map(Duration::from_secs));
+    ftp.set_abort_handle(ABORT.clone());
+    ftp.login(&creds.username, || creds.password())?;
+    ftp.configure()?;
+    Ok(ftp)
+}
+
+/**
+ * This function is to list the files of a remote directory and of its subdirectories as
+ * download jobs. The local directories are created on the way, so the local tree has the
+ * same shape. A name that isn't a single path component (e.g. "..") is never used locally.
+ * ftp: A logged-in FtpStream.
+ * cli: The command line arguments.
+ * typecode: The typecode of the URL, if any.
+ * dir: The path of the remote directory, "" or ending with '/'.
+ * local_dir: The local directory the files go to.
+ * jobs: Where the jobs are added.
+ * skipped: Where the entries that are neither files nor directories are added.
+ * Return Ok(()) if no error occurs, otherwise Err(FtpError) with error message.
+ */
+fn remote_tree(
+    ftp: &mut FtpStream,
+    cli: &Cli,
+    typecode: Option<TransferType>,
+    dir: &str,
+    local_dir: &Path,
+    jobs: &mut Vec<TransferJob>,
+    skipped: &mut Vec<String>,
+) -> Result<(), FtpError> {
+    fs::create_dir_all(local_dir)
+        .map_err(|e| FtpError::LocalIo(format!("can't create {}: {}", local_dir.display(), e)))?;
+
+    for entry in ftp.list_entries(dir)? {
+        let server_path = format!("{}{}", dir, entry.name);
+        if entry.name.contains(['/', '\\']) || entry.name == "." || entry.name == ".." {
+            skipped.push(server_path);
+            continue;
+        }
+        let local_path = local_dir.join(&entry.name);
+        match entry.kind.as_deref() {
+            Some("dir") => remote_tree(ftp, cli, typecode, &format!("{}/", server_path), &local_path, jobs, skipped)?,
+            // A symbolic link is downloaded as the file it points to
+            None | Some("file") | Some("os.unix=symlink") => jobs.push(TransferJob {
+                local_path: local_path.to_string_lossy().to_string(),
+                transfer_type: transfer_type(cli, typecode, &entry.name),
+                server_path,
+                upload: false,
+            }),
+            Some(kind) if kind.starts_with("os.unix=slink") => jobs.push(TransferJob {
+                local_path: local_path.to_string_lossy().to_string(),
+                transfer_type: transfer_type(cli, typecode, &entry.name),
+                server_path,
+                upload: false,
+            }),
+            Some(_) => skipped.push(server_path),
+        }
+    }
+    Ok(())
+}
+
+/**
+ * This function is to list the files of a local directory and of its subdirectories as
+ * upload jobs. A symbolic link to a file is uploaded as the file, a symbolic link to a
+ * directory isn't followed.
+ * cli: The command line arguments.
+ * typecode: The typecode of the URL, if any.
+ * dir: The local directory.
+ * remote_dir: The path of the remote directory the files go to, "" for the login directory.
+ * jobs: Where the jobs are added.
+ * dirs: Where the remote directories to create are added, parents first.
+ * skipped: Where the entries that are neither files nor directories are added.
+ * Return Ok(()) if no error occurs, otherwise Err(FtpError) with error message.
+ */
+fn local_tree(
+    cli: &Cli,
+    typecode: Option<TransferType>,
+    dir: &Path,
+    remote_dir: &str,
+    jobs: &mut Vec<TransferJob>,
+    dirs: &mut Vec<String>,
+    skipped: &mut Vec<String>,
+) -> Result<(), FtpError> {
+    let local_io = |e| FtpError::LocalIo(format!("can't read {}: {}", dir.display(), e));
+    let mut entries = fs::read_dir(dir)
+        .map_err(local_io)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(local_io)?;
+    entries.sort_by_key(|entry| entry.file_name());
 
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let server_path = match remote_dir.is_empty() {
+            true => name.clone(),
+            false => format!("{}/{}", remote_dir.trim_end_matches('/'), name),
+        };
+        let local_path = entry.path();
+        if entry.file_type().map_err(local_io)?.is_dir() {
+            dirs.push(server_path.clone());
+            local_tree(cli, typecode, &local_path, &server_path, jobs, dirs, skipped)?;
+        } else if local_path.is_file() {
+            jobs.push(TransferJob {
+                local_path: local_path.to_string_lossy().to_string(),
+                transfer_type: transfer_type(cli, typecode, &name),
+                server_path,
+                upload: true,
+            });
+        } else {
+            skipped.push(local_path.to_string_lossy().to_string());
+        }
+    }
+    Ok(())
+}
+
+/**
+ * This function is to report the entries of a directory that are neither files nor
+ * directories, which aren't transferred.
+ * Return Ok(()) if there is none, otherwise Err(Failure), so the copy doesn't look complete.
+ */
+fn report_skipped(skipped: &[String], output: Format) -> Result<(), Failure> {
+    for path in skipped {
+        match output {
+            Format::Text => println!("[skipped] {}: not a file or a directory", path),
+            Format::Json => output::print_json(json!({
+                "type": "skipped",
+                "path": path,
+                "error": output::error_json("not a file or a directory", None),
+            })),
+        }
+    }
+    match skipped.len() {
+        0 => Ok(()),
+        n => Err(Failure::from(format!("{} entries are not files or directories and were skipped", n))),
+    }
+}
+
+/**
+ * This function is to check the options of 'cp' can be used with '-' as the local path.
+ * The stream can only be read or written once, and has no name, size, time or permissions.
+ * upload: '-' is stdin rather than stdout.
+ * Return Ok(()) if they can, otherwise Err(String) with the first option that can't.
+ */
+fn check_stdio(cli: &Cli, upload: bool) -> Result<(), String> {
+    let conflicts = [
+        (cli.operation == Operation::Mv, "mv"),
+        (cli.verify, "--verify"),
+        (cli.resume, "--resume"),
+        (cli.preserve, "--preserve"),
+        (cli.preserve_mode, "--preserve-mode"),
+        (upload && cli.password_stdin, "--password-stdin"),
+        (!upload && cli.output == Format::Json, "--output json"),
+    ];
+    match conflicts.iter().find(|(used, _)| *used) {
+        Some((_, name)) => Err(format!("{} can't be used with '-' as the local path", name)),
+        None => Ok(()),
+    }
+}
+
+/**
+ * This function is to tell whether a profile of the configuration file is used by the
+ * parameters, so the file has to be read.
+ */
+fn is_profile_used(cli: &Cli) -> bool {
+    config::is_profile(&cli.param1) || cli.param2.as_deref().is_some_and(config::is_profile)
+}
+
+/**
+ * This function is to download a single file.
+ * ftp: A logged-in FtpStream.
+ * cli: The command line arguments.
+ * job: The local path and the server path of the file.
+ * connect: Creates the extra connections when the file is downloaded in segments.
+ * Return Ok(String) with success message if no error occurs,
+ * otherwise, Err(FtpError) with error message.
+ */
+fn download<C>(ftp: &mut FtpStream, cli: &Cli, job: &TransferJob, connect: C) -> Result<String, FtpError>
+where
+    C: Fn() -> Result<FtpStream, FtpError> + Sync,
+{
+    // Segments are positions in the file on the server, which don't match the local file in ASCII mode
+    match (cli.segments, job.transfer_type) {
+        (1, _) | (_, TransferType::Ascii) => ftp.retr(&job.local_path, &job.server_path, cli.resume),
+        (n, _) => scheduler::retr_segmented(ftp, connect, &job.local_path, &job.server_path, n as usize),
+    }
+}
+
+/**
+ * This function is to get the temporary name an atomic upload is stored at, which is
+ * in the same directory as the final name.
+ * server_path: The final path of the file in the server.
+ * prefix: Added in front of the file name.
+ * suffix: Added after the file name.
+ */
+fn temp_path(server_path: &str, prefix: &str, suffix: &str) -> String {
+    let (dir, name) = match server_path.rfind('/') {
+        Some(i) => server_path.split_at(i + 1),
+        None => ("", server_path),
+    };
+    format!("{}{}{}{}", dir, prefix, name, suffix)
+}
+
+/**
+ * This function is to give the copy of a file the modification time of its source.
+ * job: The local path and the server path of the file.
+ * Return Ok(true) if the time is set, Ok(false) if the server can't tell or set it,
+ * otherwise, Err(FtpError) with error message.
+ */
+fn preserve_time(ftp: &mut FtpStream, job: &TransferJob) -> Result<bool, FtpError> {
+    match job.upload {
+        true => {
+            let modified = fs::metadata(&job.local_path)
+                .and_then(|m| m.modified())
+                .map_err(|e| FtpError::LocalIo(format!("{}", e)))?;
+            ftp.set_modified(&job.server_path, modified)
+        }
+        false => match ftp.modified(&job.server_path)? {
+            Some(modified) => fs::File::options()
+                .write(true)
+                .open(&job.local_path)
+                .and_then(|f| f.set_modified(modified))
+                .map(|_| true)
+                .map_err(|e| FtpError::LocalIo(format!("{}", e))),
+            None => Ok(false),
+        },
+    }
+}
+
+/**
+ * This function is to give an uploaded file the permissions of the local file.
+ * job: The local path and the server path of the file.
+ * Return Ok(true) if the permissions are set, Ok(false) if the local file system has
+ * no Unix permissions, otherwise, Err(FtpError) with error message.
+ */
+#[cfg(unix)]
+fn preserve_mode(ftp: &mut FtpStream, job: &TransferJob) -> Result<bool, FtpError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(&job.local_path)
+        .map_err(|e| FtpError::LocalIo(format!("{}", e)))?
+        .permissions()
+        .mode();
+    ftp.chmod(&job.server_path, mode).map(|_| true)
+}
+
+#[cfg(not(unix))]
+fn preserve_mode(_ftp: &mut FtpStream, _job: &TransferJob) -> Result<bool, FtpError> {
+    Ok(false)
+}
+
+/**
+ * This function is to copy a single file and verify the copy if requested.
+ * ftp: A logged-in FtpStream.
+ * cli: The command line arguments.
+ * job: The local path and the server path of the file.
+ * connect: Creates the extra connections when the file is downloaded in segments.
+ * done: Where the completed steps, the size and the checksum are recorded.
+ * Return Ok(()) if no error occurs, otherwise, Err(Failure) with error message.
+ */
+fn copy<C>(ftp: &mut FtpStream, cli: &Cli, job: &TransferJob, connect: C, done: &mut Transferred) -> Result<(), Failure>
+where
+    C: Fn() -> Result<FtpStream, FtpError> + Sync,
+{
+    // Step 1: copy, the transfer is only complete once the server confirmed it with 2xx
+    let received = ftp.set_type(job.transfer_type)
+        .and_then(|_| match (job.upload, job.local_path == STDIO) {
+            (true, true) => ftp.stor_from(io::stdin().lock(), &job.server_path).map(|_| None),
+            (true, false) => ftp.stor(&job.local_path, &job.server_path).map(|_| None),
+            (false, true) => ftp.retr_to(&job.server_path, 0, &mut io::stdout().lock()).map(Some),
+            (false, false) => download(ftp, cli, job, connect).map(|_| None),
+        })
+    .map_err(|e| Failure::from(e).context(format!("{}: copy failed, the source is kept", job)))?;
+    done.steps.push("copied");
+    done.bytes = match (received, job.local_path == STDIO) {
+        (Some(n), _) => Some(n),
+        (None, false) => fs::metadata(&job.local_path).ok().map(|m| m.len()),
+        (None, true) => None,
+    };
+
+    // The modification time is left as it is if the server doesn't support it
+    if cli.preserve {
+        let preserved = preserve_time(ftp, job).map_err(|e| {
+            Failure::from(e).context(format!("{}: can't preserve the modification time, the source and the copy are kept", job))
+        })?;
+        if preserved {
+            done.steps.push("preserved the time");
+        }
+    }
+    if cli.preserve_mode && job.upload {
+        let preserved = preserve_mode(ftp, job).map_err(|e| {
+            Failure::from(e).context(format!("{}: can't preserve the permissions, the source and the copy are kept", job))
+        })?;
+        if preserved {
+            done.steps.push("preserved the permissions");
+        }
+    }
+
+    // Step 2: verify
+    if cli.verify && job.transfer_type == TransferType::Ascii {
+        return Err(Failure::from(format!("{}: can't verify an ASCII mode transfer, the source and the copy are kept", job)));
+    }
+    if cli.verify {
+        done.checksum = verify::verify(ftp, &job.local_path, &job.server_path)
+            .map_err(|e| Failure::from(e).context(format!("{}: verification failed, the source and the copy are kept", job)))?;
+        done.steps.push("verified");
+    }
+
+    Ok(())
+}
+
+/**
+ * This function is to stop a transfer between its steps once Ctrl-C was pressed.
+ * Return Ok(()) if the transfers aren't aborted, otherwise, Err(Failure) with error message.
+ */
+fn check_abort(job: &TransferJob) -> Result<(), Failure> {
+    match ABORT.is_aborted() {
+        true => Err(Failure::from(FtpError::Aborted("the transfer was aborted".to_string()))
+            .context(format!("{}: aborted after the copy, the source is kept", job))),
+        false => Ok(()),
+    }
+}
+
+/**
+ * This function is to execute 'cp' or 'mv' for a single file. 'mv' is done in steps:
+ * the file is copied, the copy is verified if requested, and only then the source is
+ * deleted. A failing step stops the move, so the source is never deleted unless the
+ * copy is known to be complete, and the copy is never deleted once it's complete.
+ * With --atomic, an upload is copied and verified under a temporary name, which is
+ * renamed to the final name afterwards, or deleted if any of these steps fails.
+ * ftp: A logged-in FtpStream.
+ * cli: The command line arguments, the operation is either Operation::Cp or Operation::Mv.
+ * job: The local path and the server path of the file.
+ * connect: Creates the extra connections when the file is downloaded in segments.
+ * Return Ok(Transferred) with the completed steps if no error occurs,
+ * otherwise, Err(Failure) with the failed step and the state both files are left in.
+ */
+fn transfer<C>(ftp: &mut FtpStream, cli: &Cli, job: &TransferJob, connect: C) -> Result<Transferred, Failure>
+where
+    C: Fn() -> Result<FtpStream, FtpError> + Sync,
+{
+    let start = Instant::now();
+    let mut done = Transferred {
+        job: job.clone(),
+        steps: vec![],
+        bytes: None,
+        duration: Duration::ZERO,
+        checksum: None,
+    };
+    let temp = match job.upload && cli.atomic {
+        true => Some(TransferJob {
+            server_path: temp_path(&job.server_path, &cli.temp_prefix, &cli.temp_suffix),
+            ..job.clone()
+        }),
+        false => None,
+    };
+
+    // A Ctrl-C during the verification or the preservation doesn't make them fail, but the
+    // steps that can't be undone don't run after it
+    let copied = copy(ftp, cli, temp.as_ref().unwrap_or(job), connect, &mut done).and_then(|_| check_abort(job));
+    // Step 3: rename an atomic upload to its final name
+    let copied = match (&temp, copied) {
+        (Some(temp), Ok(())) => ftp
+            .rename(&temp.server_path, &job.server_path)
+            .map(|_| done.steps.push("renamed"))
+            .map_err(|e| Failure::from(e).context(format!("{}: can't rename {}, the source is kept", job, temp.server_path))),
+        (_, copied) => copied,
+    };
+    if let (Some(temp), Err(_)) = (&temp, &copied) {
+        // Don't leave the temporary file behind, it may not even exist
+        let _ = ftp.dele(&temp.server_path);
+    }
+    copied?;
+
+    // Step 4: delete the source
+    if cli.operation == Operation::Mv {
+        check_abort(job)?;
+        match job.upload {
+            true => fs::remove_file(&job.local_path).map_err(|e| FtpError::LocalIo(format!("{}", e))),
+            false => ftp.dele(&job.server_path).map(|_| ()),
+        }
+        .map_err(|e| {
+            Failure::from(e).context(format!("{}: copied but can't delete the source, the source and the copy are kept", job))
+        })?;
+        done.steps.push("deleted the source");
+    }
+
+    done.duration = start.elapsed();
+    Ok(done)
+}
+
+/**
+ * This function is to run the transfer jobs over `parallel` connections and report
+ * the result of every file in the order the jobs were given. The per-file report is
+ * printed when more than one file is transferred, and the success of a single file
+ * is reported as well when `report` is set. In JSON, the success of every file is reported.
+ * Return Ok(()) if every transfer succeeds, otherwise Err(Failure) with a summary, whose
+ * class is the one all the failed files share, or General if they differ.
+ */
+fn run_transfers<C, W>(
+    jobs: Vec<TransferJob>,
+    parallel: usize,
+    report: bool,
+    output: Format,
+    connect: C,
+    work: W,
+) -> Result<(), Failure>
+where
+    C: Fn() -> Result<FtpStream, FtpError> + Sync,
+    W: Fn(&mut FtpStream, &TransferJob) -> Result<Transferred, Failure> + Sync,
+{
+    let batch = jobs.len() > 1;
+    let results = scheduler::run(&jobs, parallel, connect, work, |job, res| {
+        // The error of a single file is returned instead, so it's not printed twice
+        match (res, output) {
+            (Ok(done), Format::Text) if batch || report => println!("[ok] {}", done),
+            (Err(e), Format::Text) if batch => println!("[failed] {}", e),
+            (Ok(done), Format::Json) => output::print_json(done.to_json()),
+            (Err(e), Format::Json) if batch => {
+                let (source, destination) = job.endpoints();
+                output::print_json(json!({
+                    "type": "transfer",
+                    "ok": false,
+                    "source": source,
+                    "destination": destination,
+                    "error": output::error_json(&e.message, e.code),
+                }));
+            }
+            _ => {}
+        }
+    });
+
+    let total = results.len();
+    let failed: Vec<Failure> = results.into_iter().filter_map(|r| r.err()).collect();
+    match (failed.len(), batch) {
+        (0, _) => Ok(()),
+        (_, false) => Err(failed.into_iter().next().unwrap()),
+        (n, true) => Err(Failure {
+            kind: match failed.iter().all(|f| f.kind == failed[0].kind) {
+                true => failed[0].kind,
+                false => Kind::General,
+            },
+            message: format!("{} of {} transfers failed", n, total),
+            code: None,
+        }),
+    }
+}
+
+/**
+ * This is the main driver of this CLI. An error is printed to stderr, and the process exits
+ * with the code of its class (see exit::EXIT_CODES). With --output json, the error is printed
+ * to stdout as a JSON object as well, so scripts find every result in the same stream.
+ */
+fn main() -> ExitCode {
     // Parse the command line argument
     let cli = Cli::parse();
+    let format = cli.output;
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(mut e) => {
+            // Whatever else failed, the transfers didn't finish because of Ctrl-C
+            if ABORT.is_aborted() {
+                e.kind = Kind::Interrupted;
+            }
+            if format == Format::Json {
+                output::print_json(json!({
+                    "type": "error",
+                    "exit_code": e.kind as u8,
+                    "error": output::error_json(&e.message, e.code),
+                }));
+            }
+            eprintln!("Error: {}", e);
+            e.exit_code()
+        }
+    }
+}
+
+/**
+ * This function is to execute the operation of the command line.
+ * Return Ok(()) if the operation succeeds, otherwise Err(Failure) with error message.
+ */
+fn run(cli: Cli) -> Result<(), Failure> {
+    let level = match (cli.log_level, cli.verbose || cli.log_file.is_some()) {
+        (Some(level), _) => level,
+        (None, true) => Level::Debug,
+        (None, false) => Level::Off,
+    };
+    logger::init(level, cli.log_file.as_deref()).map_err(Failure::usage)?;
+    // The first Ctrl-C aborts the transfers with ABOR, so the files are left in a known state
+    // and the steps after them (e.g. deleting the source of 'mv') don't run. The second quits
+    ctrlc::set_handler(|| {
+        if ABORT.is_aborted() {
+            process::exit(Kind::Interrupted as i32);
+        }
+        eprintln!("Aborting the transfers, press Ctrl-C again to quit now");
+        ABORT.abort();
+    })
+    .map_err(|e| format!("can't handle Ctrl-C: {}", e))?;
+    let parallel = cli.parallel as usize;
+    // Every step of 'mv' is reported, so it's clear which side holds the file
+    let report = cli.operation == Operation::Mv;
+    // '-' has to be checked before anything is read from stdin
+    if matches!(cli.operation, Operation::Cp | Operation::Mv) {
+        if cli.param1 == STDIO {
+            check_stdio(&cli, true).map_err(Failure::usage)?;
+        }
+        if cli.param2.as_deref() == Some(STDIO) {
+            check_stdio(&cli, false).map_err(Failure::usage)?;
+        }
+    }
+    // The password is read from stdin once and shared by every connection
+    let stdin_password = match cli.password_stdin {
+        true => Some(credentials::read_stdin_password()?),
+        false => None,
+    };
+    // The configuration file is only needed when a profile is used
+    let config = match is_profile_used(&cli) {
+        true => Config::load(cli.config.as_deref())
+            .map_err(|e| Failure::usage(format!("{} (@name is a profile, write ./@name for a local path)", e)))?,
+        false => Config::default(),
+    };
 
     // Check the operation kind
     match &cli.operation {
-        Operation::Ls | Operation::Mkdir | Operation::Rm | Operation::Rmdir => {
+        Operation::Ls | Operation::Mkdir | Operation::Rm | Operation::Rmdir | Operation::Stat => {
             // Extract the parameters that would be used to create the FtpStream and login to the Ftp server
-            let (username, password, host, port, path) = match extract_param(&cli.param1) {
-                Ok(x) => x,
-                Err(e) => {return Err(e)}
-            };
+            let (url, profile) = remote(&config, &cli.param1).map_err(Failure::usage)?;
+            let path = &url.path();
+            let creds = Credentials::resolve(&url, &profile, stdin_password);
 
-            // Create the control channel with the ftp server
-            let mut ftp = match FtpStream::new(host, if !port.is_empty() {port} else {"21"}, cli.verbose) {
-                Ok(stream) => stream,
-                Err(e) => {
-                    return Err(e);
-                }
-            };
-
-            // Login to Ftp server
-            ftp.login(username, password)?;
+            // Create the control channel with the ftp server and login
+            let mut ftp = connect(&cli, &url, &profile, &creds)?;
 
             // Execute desired command
-            match &cli.operation {
-                Operation::Ls => {
+            let reply = match (&cli.operation, cli.output) {
+                (Operation::Ls, Format::Text) => {
                     ftp.list(path)?;
+                    None
                 },
-                Operation::Mkdir => {
-                    ftp.mkd(path)?;
+                (Operation::Ls, Format::Json) => {
+                    let entries: Vec<_> = ftp.list_entries(path)?.iter().map(output::stat_json).collect();
+                    output::print_json(json!({"type": "listing", "path": path, "entries": entries}));
+                    None
                 },
-                Operation::Rm => {
-                    ftp.dele(path)?;
+                (Operation::Mkdir, _) => Some(ftp.mkd(path)?),
+                (Operation::Rm, _) => Some(ftp.dele(path)?),
+                (Operation::Rmdir, _) => Some(ftp.rmd(path)?),
+                (Operation::Stat, Format::Text) => {
+                    println!("{}", ftp.stat_path(path)?);
+                    None
                 },
-                Operation::Rmdir => {
-                    ftp.rmd(path)?;
+                (Operation::Stat, Format::Json) => {
+                    let stat = output::stat_json(&ftp.stat_path(path)?);
+                    output::print_json(json!({"type": "stat", "path": path, "entry": stat}));
+                    None
                 }
-                _ => {}
+                _ => None
+            };
+
+            if let (Some(reply), Format::Json) = (reply, cli.output) {
+                output::print_json(output::reply_json(operation_name(&cli.operation), path, &reply));
             }
+            Ok(())
+        },
+        Operation::Chmod => {
+            // The mode is in octal, like chmod(1), and the URL is the second parameter
+            let mode = u32::from_str_radix(&cli.param1, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .ok_or_else(|| Failure::usage(format!("{} is not an octal mode, e.g. 755", cli.param1)))?;
+            let p = cli.param2.as_ref().ok_or_else(|| Failure::usage("Didn't provide the URL for 'chmod' command"))?;
+            let (url, profile) = remote(&config, p).map_err(Failure::usage)?;
+            let creds = Credentials::resolve(&url, &profile, stdin_password);
+
+            let mut ftp = connect(&cli, &url, &profile, &creds)?;
+            let reply = ftp.chmod(&url.path(), mode)?;
 
+            if cli.output == Format::Json {
+                output::print_json(output::reply_json("chmod", &url.path(), &reply));
+            }
             Ok(())
         },
+        Operation::Quote => {
+            // The command is the first parameter, and runs in the directory of the URL if it has a path
+            let p = cli.param2.as_ref().ok_or_else(|| Failure::usage("Didn't provide the URL for 'quote' command"))?;
+            let (url, profile) = remote(&config, p).map_err(Failure::usage)?;
+            let path = &url.path();
+            let creds = Credentials::resolve(&url, &profile, stdin_password);
+
+            let mut ftp = connect(&cli, &url, &profile, &creds)?;
+            if !path.is_empty() {
+                ftp.cwd(path)?;
+            }
+            // In JSON, the data is kept to be put into the same object as the reply
+            let mut data = vec![];
+            let reply = match (cli.data, cli.output) {
+                (true, Format::Text) => ftp.raw_command_data(&cli.param1, &mut io::stdout())?,
+                (true, Format::Json) => ftp.raw_command_data(&cli.param1, &mut data)?,
+                (false, _) => ftp.raw_command(&cli.param1)?,
+            };
+            match cli.output {
+                Format::Text => println!("{}", reply),
+                Format::Json => {
+                    let mut value = output::reply_json("quote", path, &reply.text);
+                    value["command"] = json!(cli.param1);
+                    if cli.data {
+                        value["data"] = json!(ftp.decode(&data));
+                    }
+                    output::print_json(value);
+                }
+            }
+
+            match reply.is_failure() {
+                true => Err(Failure::from(FtpError::Reply(reply)).context(format!("{} failed", cli.param1))),
+                false => Ok(()),
+            }
+        },
         _ => {
             // Check if the second parameter is entered
             match &cli.param2 {
                 Some(p) => {
-                    let r1 = extract_param(&cli.param1);
-                    let r2 = extract_param(&p);
+                    // Anything that isn't an ftp:// URL or a @profile is a local path
+                    let r1 = is_remote(&cli.param1).then(|| remote(&config, &cli.param1)).transpose().map_err(Failure::usage)?;
+                    let r2 = is_remote(p).then(|| remote(&config, p)).transpose().map_err(Failure::usage)?;
 
                     match (r1, r2){
                         // From server (param1 is an URL and param2 is a local path)
-                        (Ok((username, password, host, port, path)), Err(_)) => {
-                
-                            // Create the control channel to the Ftp server
-                            let mut ftp = match FtpStream::new(host, if !port.is_empty() {port} else {"21"}, cli.verbose) {
-                                Ok(stream) => stream,
-                                Err(e) => {
-                                    return Err(e);
-                                }
+                        (Some((url, profile)), None) => {
+                            let (path, typecode) = (&url.path(), url.transfer_type());
+                            let creds = Credentials::resolve(&url, &profile, stdin_password);
+                            let connect = || connect(&cli, &url, &profile, &creds);
+
+                            // A path ending with '/' means every file in that remote directory and
+                            // in its subdirectories
+                            let mut skipped = vec![];
+                            let jobs = if (path.is_empty() || path.ends_with('/')) && p == STDIO {
+                                return Err(Failure::usage("A directory can't be written to stdout, the URL must be a file"));
+                            } else if path.is_empty() || path.ends_with('/') {
+                                let mut jobs = vec![];
+                                remote_tree(&mut connect()?, &cli, typecode, path, Path::new(p), &mut jobs, &mut skipped)?;
+                                jobs
+                            } else {
+                                vec![TransferJob {
+                                    local_path: p.clone(),
+                                    server_path: path.to_string(),
+                                    upload: false,
+                                    transfer_type: transfer_type(&cli, typecode, path),
+                                }]
                             };
 
-                            // Login to the Ftp server
-                            ftp.login(username, password)?;
-
-                            match &cli.operation {
-                                Operation::Cp => {
-                                    ftp.retr(&p, path)?;
-                                },
-                                Operation::Mv => {
-                                    ftp.retr(&p, path)?;
-                                    // Remove the local file if the file deletion is not successful on the server side
-                                    if let Err(_) = ftp.dele(path) {
-                                        fs::remove_file(&p).map_err(|e| format!("{}", e))?;
-                                    }
-                                }
-                                _ => {}
-                            }
+                            let transferred = run_transfers(jobs, parallel, report, cli.output, connect, |ftp, job| transfer(ftp, &cli, job, connect));
+                            report_skipped(&skipped, cli.output).and(transferred)?;
                         },
                         // To server (param1 is a local path and param2 is an URL)
-                        (Err(_), Ok((username, password, host, port, path))) => {
-                            // Create the control channel to the Ftp server
-                            let mut ftp = match FtpStream::new(host, if !port.is_empty() {port} else {"21"}, cli.verbose) {
-                                Ok(stream) => stream,
-                                Err(e) => {
-                                    return Err(e);
+                        (None, Some((url, profile))) => {
+                            let (path, typecode) = (&url.path(), url.transfer_type());
+                            let creds = Credentials::resolve(&url, &profile, stdin_password);
+                            let connect = || connect(&cli, &url, &profile, &creds);
+
+                            // A local directory means every file in it and in its subdirectories goes
+                            // to the remote directory
+                            let mut skipped = vec![];
+                            let jobs = if Path::new(&cli.param1).is_dir() {
+                                let (mut jobs, mut dirs) = (vec![], vec![]);
+                                if !path.is_empty() {
+                                    dirs.push(path.trim_end_matches('/').to_string());
                                 }
+                                local_tree(&cli, typecode, Path::new(&cli.param1), path, &mut jobs, &mut dirs, &mut skipped)?;
+                                if !dirs.is_empty() {
+                                    let mut ftp = connect()?;
+                                    for dir in &dirs {
+                                        // The directory may exist already, if it can't be created the uploads into it fail
+                                        let _ = ftp.mkd(dir);
+                                    }
+                                }
+                                jobs
+                            } else {
+                                vec![TransferJob {
+                                    local_path: cli.param1.clone(),
+                                    server_path: path.to_string(),
+                                    upload: true,
+                                    transfer_type: transfer_type(&cli, typecode, path),
+                                }]
                             };
 
-                            // Login to the Ftp server
-                            ftp.login(username, password)?;
-
-                            match &cli.operation {
-                                Operation::Cp => {
-                                    ftp.stor(&cli.param1, path)?;
-                                },
-                                Operation::Mv => {
-                                    ftp.stor(&cli.param1, path)?;
-                                    // Remove the local file if the file successfully makes its way to the server
-                                    fs::remove_file(&cli.param1).map_err(|e| format!("{}", e))?;
-                                },
-                                _ => {}
-                            }
-
+                            let transferred = run_transfers(jobs, parallel, report, cli.output, connect, |ftp, job| transfer(ftp, &cli, job, connect));
+                            report_skipped(&skipped, cli.output).and(transferred)?;
                         },
-                        _ => {return Err("If ARG1 is a local file, then ARG2 must be a URL, and vice-versa.".to_string());}
+                        _ => {return Err(Failure::usage("If ARG1 is a local file, then ARG2 must be a URL, and vice-versa."));}
 
                     }
                     
                 },
-                None => {return Err("Didn't provide the second argument for \'cp\' or \'mv\' command".to_string());}
+                None => {return Err(Failure::usage("Didn't provide the second argument for \'cp\' or \'mv\' command"));}
             }
 
             Ok(())
@@ -0,0 +1,120 @@
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+
+use crate::stat::{self, FileStat};
+
+/**
+ * This enum is the format the results are printed in (--output). In JSON, every result
+ * is a single line with one JSON object, whose "type" tells what it is.
+ */
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/**
+ * This function is to print a JSON object on a single line to stdout.
+ */
+pub fn print_json(value: Value) {
+    println!("{}", value);
+}
+
+/**
+ * This function is to describe a file on the Ftp server as a JSON object, e.g.
+ * {"name": "a.txt", "type": "file", "size": 12, "modified": "2024-01-01T12:00:00Z", "facts": {...}}.
+ * Whatever the server didn't tell is null.
+ */
+pub fn stat_json(stat: &FileStat) -> Value {
+    let facts: Map<String, Value> = stat
+        .facts
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+        .collect();
+    json!({
+        "name": stat.name,
+        "type": stat.kind,
+        "size": stat.size,
+        "modified": stat.modified.map(stat::format_iso),
+        "facts": facts,
+    })
+}
+
+/**
+ * This function is to describe a reply of the Ftp server to an operation as a JSON object.
+ * operation: The name of the operation, e.g. "mkdir".
+ * path: The path the operation was executed on.
+ * reply: The full reply.
+ */
+pub fn reply_json(operation: &str, path: &str, reply: &str) -> Value {
+    json!({
+        "type": "reply",
+        "operation": operation,
+        "path": path,
+        "code": reply_code(reply),
+        "reply": reply,
+    })
+}
+
+/**
+ * This function is to describe an error as a JSON object.
+ * message: The error message.
+ * code: The code of the server reply the error carries, or None (null) if it didn't come
+ * from the server.
+ */
+pub fn error_json(message: &str, code: Option<u16>) -> Value {
+    json!({
+        "message": message,
+        "code": code,
+    })
+}
+
+/**
+ * This function is to get the code of a server reply, which starts with a code of 1xx to
+ * 5xx followed by a space, a '-' or nothing, e.g. "550 No such file".
+ * Return Some(u16) with the code, otherwise None.
+ */
+pub fn reply_code(reply: &str) -> Option<u16> {
+    let bytes = reply.as_bytes();
+    match (bytes.get(..3), bytes.get(3)) {
+        (Some(code), Some(b' ') | Some(b'-') | None)
+            if (b'1'..=b'5').contains(&code[0]) && code.iter().all(u8::is_ascii_digit) =>
+        {
+            reply[..3].parse().ok()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_of_a_reply() {
+        assert_eq!(reply_code("550 No such file"), Some(550));
+        assert_eq!(reply_code("211-Features:\n SIZE\n211 End"), Some(211));
+        assert_eq!(reply_code("200"), Some(200));
+        assert_eq!(reply_code("150 Opening data channel"), Some(150));
+    }
+
+    #[test]
+    fn no_code() {
+        assert_eq!(reply_code(""), None);
+        assert_eq!(reply_code("55"), None);
+        assert_eq!(reply_code("5500 too long"), None);
+        assert_eq!(reply_code("650 out of range"), None);
+        assert_eq!(reply_code("012 out of range"), None);
+        assert_eq!(reply_code("a.txt: 550 No such file"), None);
+        assert_eq!(reply_code("2024-01-01 is a date"), None);
+    }
+
+    #[test]
+    fn error_and_reply_objects() {
+        assert_eq!(error_json("550 No such file", Some(550)), json!({"message": "550 No such file", "code": 550}));
+        assert_eq!(error_json("connection failed", None)["code"], Value::Null);
+        let reply = reply_json("mkdir", "dir", "257 \"dir\" created");
+        assert_eq!(reply["code"], 257);
+        assert_eq!(reply["type"], "reply");
+    }
+}
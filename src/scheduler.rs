@@ -0,0 +1,196 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{Seek, SeekFrom},
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use crate::ftp::{self, FtpError, FtpStream};
+
+/**
+ * This function is to execute the given jobs over several logged-in FtpStreams at the same time.
+ * Every connection runs on its own thread and keeps taking the next unstarted job until
+ * there is none left, so slow files don't hold up the other connections.
+ * A connection whose control channel is lost (see FtpStream::is_lost) is replaced by a new
+ * one, and the job it failed is put back to be tried once more, as it may have failed only
+ * because of the connection. If no new connection can be made, the thread stops and the
+ * other connections take the remaining jobs.
+ * jobs: The jobs to be executed. Every job is executed once, or twice if its connection is lost.
+ * connections: The number of connections to open. It's capped by the number of jobs.
+ * connect: Creates a new logged-in FtpStream. It's called once for every connection, and
+ * again whenever a connection is lost.
+ * work: Executes a single job with the given FtpStream.
+ * report: Called with every job and its result, in the order the jobs were given.
+ * Return a Vec with the result of every job, in the order the jobs were given. The jobs
+ * no connection could take fail with the error of the last connection that failed.
+ */
+pub fn run<J, T, E, C, W, R>(
+    jobs: &[J],
+    connections: usize,
+    connect: C,
+    work: W,
+    mut report: R,
+) -> Vec<Result<T, E>>
+where
+    J: Sync,
+    T: Send,
+    E: Send + From<FtpError>,
+    C: Fn() -> Result<FtpStream, FtpError> + Sync,
+    W: Fn(&mut FtpStream, &J) -> Result<T, E> + Sync,
+    R: FnMut(&J, &Result<T, E>),
+{
+    let connections = connections.clamp(1, jobs.len().max(1));
+    // The jobs that haven't been taken by any connection, and whether they were tried already
+    let queue: Mutex<VecDeque<(usize, bool)>> = Mutex::new((0..jobs.len()).map(|i| (i, false)).collect());
+    // The last connection error, used for the jobs no connection could take
+    let connect_err: Mutex<Option<FtpError>> = Mutex::new(None);
+    let mut results: Vec<Option<Result<T, E>>> = jobs.iter().map(|_| None).collect();
+    let mut reported = 0;
+
+    thread::scope(|s| {
+        let (tx, rx) = mpsc::channel();
+
+        for _ in 0..connections {
+            let tx = tx.clone();
+            let (queue, connect_err, connect, work) = (&queue, &connect_err, &connect, &work);
+
+            s.spawn(move || {
+                let mut ftp = match connect() {
+                    Ok(ftp) => ftp,
+                    Err(e) => {
+                        *connect_err.lock().unwrap() = Some(e);
+                        return;
+                    }
+                };
+
+                loop {
+                    let (i, retried) = match queue.lock().unwrap().pop_front() {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    let res = work(&mut ftp, &jobs[i]);
+                    let lost = res.is_err() && ftp.is_lost();
+
+                    // The job goes first, so the results can still be reported in order soon
+                    if lost && !retried {
+                        queue.lock().unwrap().push_front((i, true));
+                    } else if tx.send((i, res)).is_err() {
+                        break;
+                    }
+                    if lost {
+                        match connect() {
+                            Ok(new) => ftp = new,
+                            Err(e) => {
+                                *connect_err.lock().unwrap() = Some(e);
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Results arrive in completion order, but they are reported in job order
+        for (i, res) in rx {
+            results[i] = Some(res);
+            while let Some(Some(res)) = results.get(reported) {
+                report(&jobs[reported], res);
+                reported += 1;
+            }
+        }
+    });
+
+    // The jobs without a result are the ones no connection could take
+    let connect_err = connect_err
+        .into_inner()
+        .unwrap()
+        .unwrap_or_else(|| FtpError::Connection("no connection to the server".to_string()));
+    for (job, res) in jobs.iter().zip(results.iter_mut()).skip(reported) {
+        let res = res.get_or_insert_with(|| Err(E::from(connect_err.clone())));
+        report(job, res);
+    }
+
+    results.into_iter().map(|res| res.unwrap()).collect()
+}
+
+// Files smaller than this are never split, the extra connections would cost more than they save
+const MIN_SEGMENT_SIZE: u64 = 1 << 20;
+
+/**
+ * This function is to download a single file over several connections at the same time.
+ * The file is split into `segments` ranges of about the same size, every range is fetched
+ * with REST and RETR on its own connection and written at its position in the local file.
+ * The file is downloaded over `ftp` alone if the server doesn't support REST STREAM and SIZE,
+ * or the file is too small to be worth splitting.
+ * ftp: A logged-in FtpStream, used to probe the server and for the single stream fallback.
+ * connect: Creates a new logged-in FtpStream. It's called once for every segment.
+ * file_path: The path of the file that the file would be stored at after the execution.
+ * server_path: The path of the file in the server.
+ * segments: The number of ranges (and connections) to split the file into.
+ * Like FtpStream::retr, the file is received into a temporary file, which is renamed to
+ * `file_path` once every range is complete and removed otherwise.
+ * Return Ok(String) with success message if no error occurs,
+ * otherwise, Err(FtpError) with error message.
+ */
+pub fn retr_segmented<C>(
+    ftp: &mut FtpStream,
+    connect: C,
+    file_path: &str,
+    server_path: &str,
+    segments: usize,
+) -> Result<String, FtpError>
+where
+    C: Fn() -> Result<FtpStream, FtpError> + Sync,
+{
+    let size = match ftp.features().rest_stream && ftp.features().size {
+        true => ftp.size(server_path).ok(),
+        false => None,
+    };
+
+    let size = match size {
+        Some(size) if segments > 1 && size >= MIN_SEGMENT_SIZE * 2 => size,
+        _ => return ftp.retr(file_path, server_path, false),
+    };
+
+    // Split the file into (offset, len) ranges, the last range takes the remainder
+    let segments = (segments as u64).min(size / MIN_SEGMENT_SIZE);
+    let ranges: Vec<(u64, u64)> = (0..segments)
+        .map(|i| {
+            let offset = size / segments * i;
+            let end = if i + 1 == segments { size } else { size / segments * (i + 1) };
+            (offset, end - offset)
+        })
+        .collect();
+
+    // Every segment writes at its own position, so the file must have its final size first
+    let part = ftp::part_path(file_path);
+    let local_io = |e| FtpError::LocalIo(format!("can't write {}: {}", part, e));
+    File::create(&part).and_then(|f| f.set_len(size)).map_err(local_io)?;
+
+    let results = run(
+        &ranges,
+        ranges.len(),
+        connect,
+        |ftp, &(offset, len)| {
+            let mut f = File::options().write(true).open(&part).map_err(local_io)?;
+            f.seek(SeekFrom::Start(offset)).map_err(local_io)?;
+            ftp.retr_range(server_path, offset, len, &mut f)
+        },
+        |_, _| {},
+    );
+
+    // Don't leave a file behind that has holes in it
+    if let Some(e) = results.into_iter().find_map(|res| res.err()) {
+        let _ = fs::remove_file(&part);
+        return Err(e);
+    }
+    let f = File::options().write(true).open(&part).map_err(local_io)?;
+    ftp::commit_part(f, &part, file_path)?;
+
+    Ok(format!(
+        "Successfully transfered {} to {} in {} segments",
+        server_path, file_path, segments
+    ))
+}
@@ -0,0 +1,287 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/**
+ * This struct is the metadata of a single file on the Ftp server, from a MLST reply
+ * (RFC 3659) or a line of a LIST reply. Anything the server didn't tell is None.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileStat {
+    pub name: String,
+    /// The type, lowercased, e.g. "file", "dir" or "os.unix=symlink"
+    pub kind: Option<String>,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    /// Every MLST fact with its name lowercased, e.g. ("perm", "adfrw") or ("unix.mode", "0644")
+    pub facts: Vec<(String, String)>,
+}
+
+impl FileStat {
+    /**
+     * This function is to parse the entry of a MLST reply, e.g.
+     * "type=file;size=1024;modify=20240101120000; /dir/file.txt".
+     * Return Some(FileStat) if it's a valid entry, otherwise None.
+     */
+    pub fn parse_mlst(entry: &str) -> Option<Self> {
+        let (facts, name) = entry.trim_start_matches(' ').split_once(' ')?;
+        let mut stat = FileStat {
+            name: name.to_string(),
+            ..FileStat::default()
+        };
+
+        for fact in facts.split(';').filter(|f| !f.is_empty()) {
+            let (key, value) = fact.split_once('=')?;
+            let key = key.to_lowercase();
+            match key.as_str() {
+                "type" => stat.kind = Some(value.to_lowercase()),
+                "size" => stat.size = value.parse().ok(),
+                "modify" => stat.modified = parse_time(value),
+                _ => {}
+            }
+            stat.facts.push((key, value.to_string()));
+        }
+
+        Some(stat)
+    }
+
+    /**
+     * This function is to parse a line of a LIST reply, in the Unix format
+     * ("-rw-r--r-- 1 owner group 1024 Jan 01 12:00 file.txt") or the DOS format
+     * ("01-01-24 12:00PM 1024 file.txt"). The time is left out, as it's in the server's
+     * time zone and may have no year.
+     * Return Some(FileStat) if the format is known, otherwise None.
+     */
+    pub fn parse_list(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let first = fields.first()?;
+
+        let (kind, size, name) = if first.len() == 10 && "-dlcbps".contains(&first[..1]) {
+            // Unix: mode links owner group size month day time|year name...
+            let kind = match &first[..1] {
+                "d" => "dir",
+                "l" => "os.unix=symlink",
+                "-" => "file",
+                _ => "os.unix=special",
+            };
+            let name = nth_field_onwards(line, 8)?;
+            // A symbolic link is shown as "name -> target"
+            let name = match kind {
+                "os.unix=symlink" => name.split(" -> ").next().unwrap_or(name),
+                _ => name,
+            };
+            (kind, fields.get(4)?.parse().ok(), name)
+        } else if fields.len() >= 4 && first.contains('-') {
+            // DOS: date time <DIR>|size name...
+            let name = nth_field_onwards(line, 3)?;
+            match fields[2] {
+                "<DIR>" => ("dir", None, name),
+                size => ("file", Some(size.parse().ok()?), name),
+            }
+        } else {
+            return None;
+        };
+
+        Some(FileStat {
+            name: name.to_string(),
+            kind: Some(kind.to_string()),
+            size,
+            ..FileStat::default()
+        })
+    }
+}
+
+impl fmt::Display for FileStat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "name: {}", self.name)?;
+        writeln!(f, "type: {}", self.kind.as_deref().unwrap_or("unknown"))?;
+        match self.size {
+            Some(size) => writeln!(f, "size: {}", size)?,
+            None => writeln!(f, "size: unknown")?,
+        }
+        match self.modified {
+            Some(t) => write!(f, "modified: {}", format_iso(t)),
+            None => write!(f, "modified: unknown"),
+        }
+    }
+}
+
+/**
+ * This function is to parse a time value of MDTM and MLST (RFC 3659 section 2.3),
+ * "YYYYMMDDHHMMSS" with optional fractions of a second (".sss"), which is always UTC.
+ * Return Some(SystemTime) if it's a valid time, otherwise None.
+ */
+pub fn parse_time(value: &str) -> Option<SystemTime> {
+    let (secs, fraction) = value.split_once('.').unwrap_or((value, "0"));
+    if secs.len() != 14 || fraction.is_empty() || !secs.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let field = |from: usize, to: usize| secs[from..to].parse::<i64>().unwrap();
+    let (y, mo, d) = (field(0, 4), field(4, 6), field(6, 8));
+    let (h, mi, s) = (field(8, 10), field(10, 12), field(12, 14));
+    if !(1..=12).contains(&mo) || !(1..=days_in_month(y, mo)).contains(&d) || h > 23 || mi > 59 || s > 60 {
+        return None;
+    }
+
+    // Only the first 9 digits fit in nanoseconds
+    let nanos = format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse::<u32>().unwrap();
+    let secs = days_from_civil(y, mo, d) * 86400 + h * 3600 + mi * 60 + s;
+    match secs >= 0 {
+        true => UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos)),
+        false => UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))?.checked_add(Duration::new(0, nanos)),
+    }
+}
+
+/**
+ * This function is to format a time as "YYYYMMDDHHMMSS" in UTC, the form MDTM and MLST
+ * give and MFMT expects. The fractions of a second are dropped.
+ */
+pub fn format_time(t: SystemTime) -> String {
+    let (y, mo, d, h, mi, s) = to_utc(t);
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}", y, mo, d, h, mi, s)
+}
+
+/**
+ * This function is to format a time as ISO 8601 in UTC, e.g. "2024-01-01T12:00:00Z".
+ * The fractions of a second are dropped.
+ */
+pub fn format_iso(t: SystemTime) -> String {
+    let (y, mo, d, h, mi, s) = to_utc(t);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", y, mo, d, h, mi, s)
+}
+
+/**
+ * This function is to split a time into year, month, day, hour, minute and second in UTC.
+ */
+fn to_utc(t: SystemTime) -> (i64, i64, i64, i64, i64, i64) {
+    let secs = match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        // Round down, so a time before 1970 doesn't end up a second later
+        Err(e) => -(e.duration().as_secs() as i64) - (e.duration().subsec_nanos() > 0) as i64,
+    };
+    let (days, rest) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (y, mo, d) = civil_from_days(days);
+    (y, mo, d, rest / 3600, rest % 3600 / 60, rest % 60)
+}
+
+/**
+ * This function is to get the number of days in a month of the proleptic Gregorian calendar.
+ */
+fn days_in_month(y: i64, m: i64) -> i64 {
+    match m {
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/**
+ * This function is to count the days from 1970-01-01 to a date of the proleptic
+ * Gregorian calendar (http://howardhinnant.github.io/date_algorithms.html).
+ */
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/**
+ * This function is to get the date that is the given number of days from 1970-01-01,
+ * the inverse of days_from_civil.
+ */
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if m <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 }, m, d)
+}
+
+/**
+ * This function is to get the rest of a line from its n-th whitespace separated field on,
+ * so a name with spaces is kept as it is.
+ */
+fn nth_field_onwards(line: &str, n: usize) -> Option<&str> {
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        rest = rest[rest.find(char::is_whitespace)?..].trim_start();
+    }
+    (!rest.is_empty()).then_some(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format_time() {
+        let t = parse_time("20240229123456").unwrap();
+        assert_eq!(format_time(t), "20240229123456");
+        assert_eq!(format_iso(t), "2024-02-29T12:34:56Z");
+        assert_eq!(parse_time("19700101000000"), Some(UNIX_EPOCH));
+        assert_eq!(parse_time("19700101000001.5"), UNIX_EPOCH.checked_add(Duration::from_millis(1500)));
+        assert_eq!(format_time(parse_time("19691231235959").unwrap()), "19691231235959");
+        assert_eq!(format_time(parse_time("20240101000000.999").unwrap()), "20240101000000");
+    }
+
+    #[test]
+    fn parse_time_rejects_invalid_dates() {
+        for value in [
+            "20240231000000",
+            "20230229000000",
+            "19000229000000",
+            "20240431000000",
+            "20241301000000",
+            "20240100000000",
+            "20240101240000",
+            "2024010100000",
+            "2024010100000x",
+            "20240101000000.",
+        ] {
+            assert_eq!(parse_time(value), None, "{}", value);
+        }
+        assert!(parse_time("20000229000000").is_some());
+        assert!(parse_time("20241231235960").is_some());
+    }
+
+    #[test]
+    fn mlst_entry() {
+        let stat = FileStat::parse_mlst(" Type=file;Size=1024;modify=20240101120000;UNIX.mode=0644; my file.txt").unwrap();
+        assert_eq!(stat.name, "my file.txt");
+        assert_eq!(stat.kind.as_deref(), Some("file"));
+        assert_eq!(stat.size, Some(1024));
+        assert_eq!(stat.modified.map(format_time).as_deref(), Some("20240101120000"));
+        assert!(stat.facts.contains(&("unix.mode".to_string(), "0644".to_string())));
+        assert_eq!(FileStat::parse_mlst("type=dir; docs").unwrap().kind.as_deref(), Some("dir"));
+        assert_eq!(FileStat::parse_mlst("no-facts"), None);
+    }
+
+    #[test]
+    fn unix_list_line() {
+        let stat = FileStat::parse_list("-rw-r--r--   1 owner group    1024 Jan 01 12:00 my  file.txt").unwrap();
+        assert_eq!((stat.name.as_str(), stat.kind.as_deref(), stat.size), ("my  file.txt", Some("file"), Some(1024)));
+        let stat = FileStat::parse_list("drwxr-xr-x 2 owner group 4096 Mar  3  2023 docs").unwrap();
+        assert_eq!((stat.name.as_str(), stat.kind.as_deref()), ("docs", Some("dir")));
+        let stat = FileStat::parse_list("lrwxrwxrwx 1 owner group 11 Jan 01 12:00 latest link -> releases/1.0").unwrap();
+        assert_eq!((stat.name.as_str(), stat.kind.as_deref()), ("latest link", Some("os.unix=symlink")));
+    }
+
+    #[test]
+    fn dos_list_line() {
+        let stat = FileStat::parse_list("01-01-24  12:00PM               1024 my file.txt").unwrap();
+        assert_eq!((stat.name.as_str(), stat.kind.as_deref(), stat.size), ("my file.txt", Some("file"), Some(1024)));
+        let stat = FileStat::parse_list("01-01-24  12:00PM       <DIR>          Program Files").unwrap();
+        assert_eq!((stat.name.as_str(), stat.kind.as_deref(), stat.size), ("Program Files", Some("dir"), None));
+        assert_eq!(FileStat::parse_list("total 8"), None);
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    features::Features,
+    ftp::{FtpError, FtpStream},
+};
+
+/**
+ * This enum lists the checksum algorithms that can be computed locally, from the
+ * most to the least preferred.
+ */
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Algorithm {
+    Sha256,
+    Md5,
+    Crc32,
+}
+
+/**
+ * This struct is the checksum a local file and its copy on the Ftp server were found to share.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Checksum {
+    /// The name of the algorithm, e.g. "SHA-256"
+    pub algorithm: &'static str,
+    /// The digest in lowercase hex
+    pub digest: String,
+}
+
+const ALGORITHMS: [Algorithm; 3] = [Algorithm::Sha256, Algorithm::Md5, Algorithm::Crc32];
+
+impl Algorithm {
+    /**
+     * The name of the algorithm used by the HASH command (draft-bryan-ftpext-hash).
+     */
+    fn hash_name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Md5 => "MD5",
+            Algorithm::Crc32 => "CRC32",
+        }
+    }
+
+    /**
+     * The non-standard command that returns the checksum with this algorithm.
+     */
+    fn x_command(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "XSHA256",
+            Algorithm::Md5 => "XMD5",
+            Algorithm::Crc32 => "XCRC",
+        }
+    }
+
+    /**
+     * The number of hex digits in a checksum of this algorithm.
+     */
+    fn hex_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 64,
+            Algorithm::Md5 => 32,
+            Algorithm::Crc32 => 8,
+        }
+    }
+
+    /**
+     * This function is to compute the checksum of a local file.
+     * Return Ok(String) with the checksum in lowercase hex if no error occurs,
+     * otherwise, Err(FtpError::LocalIo) with error message.
+     */
+    fn digest_file(self, path: &str) -> Result<String, FtpError> {
+        let local_io = |e| FtpError::LocalIo(format!("can't read {}: {}", path, e));
+        let f = BufReader::new(File::open(path).map_err(local_io)?);
+        self.digest(f).map_err(local_io)
+    }
+
+    /**
+     * This function is to compute the checksum of all the data read from a reader.
+     * Return Ok(String) with the checksum in lowercase hex if no error occurs,
+     * otherwise, Err(io::Error) if the data can't be read.
+     */
+    fn digest(self, data: impl Read) -> io::Result<String> {
+        Ok(match self {
+            Algorithm::Sha256 => {
+                let mut sha256 = Sha256::new();
+                read_chunks(data, |chunk| sha256.update(chunk))?;
+                to_hex(&sha256.finalize())
+            }
+            Algorithm::Md5 => {
+                let mut md5 = Md5::new();
+                read_chunks(data, |chunk| md5.update(chunk))?;
+                to_hex(&md5.finalize())
+            }
+            Algorithm::Crc32 => {
+                let mut crc32 = crc32fast::Hasher::new();
+                read_chunks(data, |chunk| crc32.update(chunk))?;
+                format!("{:08x}", crc32.finalize())
+            }
+        })
+    }
+
+    /**
+     * This function is to find the checksum in the reply to HASH or the X* command. It's
+     * the first word with exactly the number of hex digits of the algorithm, with or without
+     * "0x". Some servers send a CRC32 without its leading zeros, so a shorter hex word is
+     * taken for a CRC32 as well, and padded to 8 digits.
+     * res: The reply text after the reply code.
+     * Return Some(String) with the checksum in lowercase hex, otherwise None.
+     */
+    fn parse_digest(self, res: &str) -> Option<String> {
+        let words: Vec<String> = res
+            .split_whitespace()
+            .map(|w| w.trim_start_matches("0x").to_lowercase())
+            .filter(|w| !w.is_empty() && w.chars().all(|c| c.is_ascii_hexdigit()))
+            .collect();
+
+        match words.iter().find(|w| w.len() == self.hex_len()) {
+            Some(digest) => Some(digest.clone()),
+            None if self == Algorithm::Crc32 => words.iter().find(|w| w.len() < 8).map(|w| format!("{:0>8}", w)),
+            None => None,
+        }
+    }
+}
+
+/**
+ * This function is to read all the data from a reader and pass it on in chunks.
+ */
+fn read_chunks(mut data: impl Read, mut update: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match data.read(&mut buf)? {
+            0 => return Ok(()),
+            len => update(&buf[..len]),
+        }
+    }
+}
+
+/**
+ * This function is to format bytes as lowercase hex.
+ */
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+ * This function is to pick the best way to get a checksum from the server, based on
+ * the features it advertises. HASH is preferred over the X* commands.
+ * features: The features of the server.
+ * Return Some((algorithm, true)) to use HASH, Some((algorithm, false)) to use the X* command,
+ * or None if the server advertises no checksum the client can compute.
+ */
+fn select_checksum(features: &Features) -> Option<(Algorithm, bool)> {
+    ALGORITHMS
+        .iter()
+        .find(|alg| features.hash.iter().any(|n| n == alg.hash_name()))
+        .map(|&alg| (alg, true))
+        .or_else(|| {
+            ALGORITHMS
+                .iter()
+                .find(|alg| features.has(alg.x_command()))
+                .map(|&alg| (alg, false))
+        })
+}
+
+/**
+ * This function is to verify that a local file and a file on the Ftp server are identical.
+ * The sizes are always compared, and the checksums are compared as well when the server
+ * advertises HASH, XSHA256, XMD5 or XCRC.
+ * ftp: A logged-in FtpStream.
+ * local_path: The path of the file in the local storage.
+ * server_path: The path of the file in the server.
+ * Return Ok(Some(Checksum)) if both files are identical, or Ok(None) if they have the same
+ * size and there is no checksum to compare, otherwise, Err(FtpError) with error message, which
+ * is FtpError::Verify if the files differ.
+ */
+pub fn verify(ftp: &mut FtpStream, local_path: &str, server_path: &str) -> Result<Option<Checksum>, FtpError> {
+    let local_size = fs::metadata(local_path)
+        .map_err(|e| FtpError::LocalIo(format!("can't read {}: {}", local_path, e)))?
+        .len();
+    let server_size = ftp.size(server_path)?;
+    if local_size != server_size {
+        return Err(FtpError::Verify(format!(
+            "size mismatch: {} is {} bytes but {} is {} bytes",
+            local_path, local_size, server_path, server_size
+        )));
+    }
+
+    let (alg, use_hash) = match select_checksum(ftp.features()) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let res = match use_hash {
+        true => {
+            ftp.opts(&format!("HASH {}", alg.hash_name()))?;
+            ftp.checksum("HASH", server_path)?
+        }
+        false => ftp.checksum(alg.x_command(), server_path)?,
+    };
+
+    let server_digest = alg
+        .parse_digest(&res)
+        .ok_or_else(|| FtpError::Other(format!("can't find the {} checksum in the reply: {}", alg.hash_name(), res)))?;
+    let local_digest = alg.digest_file(local_path)?;
+
+    if local_digest != server_digest {
+        return Err(FtpError::Verify(format!(
+            "{} mismatch: {} is {} but {} is {}",
+            alg.hash_name(),
+            local_path,
+            local_digest,
+            server_path,
+            server_digest
+        )));
+    }
+
+    Ok(Some(Checksum {
+        algorithm: alg.hash_name(),
+        digest: server_digest,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(lines: &[&str]) -> Features {
+        Features::parse(&lines.iter().map(|l| l.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn hash_is_preferred_over_x_commands() {
+        let both = features(&["XSHA256", "XMD5", "HASH SHA-1;MD5*;CRC32"]);
+        assert_eq!(select_checksum(&both), Some((Algorithm::Md5, true)));
+        let x_only = features(&["XCRC", "XMD5"]);
+        assert_eq!(select_checksum(&x_only), Some((Algorithm::Md5, false)));
+        assert_eq!(select_checksum(&features(&["XCRC"])), Some((Algorithm::Crc32, false)));
+        // SHA-1 can't be computed locally, so the X* command is used
+        assert_eq!(select_checksum(&features(&["HASH SHA-1", "XCRC"])), Some((Algorithm::Crc32, false)));
+        assert_eq!(select_checksum(&features(&["SIZE", "MDTM"])), None);
+    }
+
+    #[test]
+    fn digest_in_reply() {
+        let sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let hash = format!("SHA-256 0-0 {} empty.txt", sha256.to_uppercase());
+        assert_eq!(Algorithm::Sha256.parse_digest(&hash).as_deref(), Some(sha256));
+        assert_eq!(Algorithm::Md5.parse_digest("D41D8CD98F00B204E9800998ECF8427E").as_deref(), Some("d41d8cd98f00b204e9800998ecf8427e"));
+        assert_eq!(Algorithm::Crc32.parse_digest("0x1A2B3C4D").as_deref(), Some("1a2b3c4d"));
+        assert_eq!(Algorithm::Md5.parse_digest("1a2b3c"), None);
+        assert_eq!(Algorithm::Sha256.parse_digest("not a checksum"), None);
+    }
+
+    #[test]
+    fn crc32_without_leading_zeros() {
+        assert_eq!(Algorithm::Crc32.parse_digest("1a2b3c").as_deref(), Some("001a2b3c"));
+        assert_eq!(Algorithm::Crc32.parse_digest("0x0").as_deref(), Some("00000000"));
+        assert_eq!(Algorithm::Crc32.parse_digest("CRC32 0-9 1a2b3c file.txt").as_deref(), Some("001a2b3c"));
+    }
+
+    #[test]
+    fn local_digests() {
+        let data: &[u8] = b"hello";
+        assert_eq!(Algorithm::Sha256.digest(data).unwrap(), "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert_eq!(Algorithm::Md5.digest(data).unwrap(), "5d41402abc4b2a76b9719d911017c592");
+        assert_eq!(Algorithm::Crc32.digest(data).unwrap(), "3610a686");
+        assert_eq!(Algorithm::Crc32.digest(&b""[..]).unwrap(), "00000000");
+    }
+}